@@ -1,9 +1,74 @@
 use std::sync::{Arc, Mutex};
 
-use crate::component::wavetable::Wavetable;
+use crate::{util::retired_queue::RetiredQueue, component::{wavetable::Wavetable, sampler::Sample, sfz::SfzInstrument, mseg::MsegShape, lfo::LFOS, lfo_shape::LFOShapeTable, spectral::SpectralPool}};
 
 pub type CommonDataRef = Arc<Mutex<CommonData>>;
 
+/// How many wavetables are loaded at once.
+pub const WAVETABLE_SLOTS: usize = 4;
+
+/// Where the host's transport is, for tempo synced and song locked components.
+#[derive(Clone, Copy)]
+pub struct Transport {
+    /// in BPM
+    pub tempo: f32,
+    /// song position at the start of the block in quarter note beats, while the host plays
+    pub position: Option<f64>,
+}
+impl Transport {
+    /// Used while the host doesn't say.
+    pub const DEFAULT_TEMPO: f32 = 120.0;
+}
+impl Default for Transport {
+    fn default() -> Self {
+        Self { tempo: Self::DEFAULT_TEMPO, position: None }
+    }
+}
+
+/// Data swapped out of `CommonData` while voices may still hold it, freed by the background
+/// thread once they let go rather than by the audio thread.
+#[derive(Default)]
+pub struct Retired {
+    pub wavetables: RetiredQueue<Wavetable>,
+    pub samples: RetiredQueue<Sample>,
+    pub lfo_tables: RetiredQueue<LFOShapeTable>,
+    pub msegs: RetiredQueue<MsegShape>,
+}
+impl Retired {
+    pub fn is_empty(&self) -> bool {
+        self.wavetables.is_empty() && self.samples.is_empty() && self.lfo_tables.is_empty()
+            && self.msegs.is_empty()
+    }
+    /// Free everything no voice holds any more. Call off the audio thread.
+    pub fn free_unused(&self) {
+        self.wavetables.free_unused();
+        self.samples.free_unused();
+        self.lfo_tables.free_unused();
+        self.msegs.free_unused();
+    }
+}
+
 pub struct CommonData {
-    pub wavetable: Wavetable,
-}
\ No newline at end of file
+    /// swapped out whole when a new table loads, so voices can keep playing the old one
+    pub wavetables: [Arc<Wavetable>; WAVETABLE_SLOTS],
+    pub sample: Arc<Sample>,
+    /// loaded in the background, voices play this instead of `sample` when it is set
+    pub instrument: Option<SfzInstrument>,
+    /// samples processed since the plugin started, the global clock free-running phases follow
+    pub clock: u64,
+    /// how many times the host rate new voices run at
+    pub oversampling: usize,
+    /// voices started since the last reset, which seeds each voice's analog variance
+    pub voices_started: u64,
+    /// replaced wavetables, samples, LFO tables and MSEG shapes, until no voice holds them
+    pub retired: Arc<Retired>,
+    /// where the host is this block, new voices take their tempo from it
+    pub transport: Transport,
+    /// copied from `TestParams::mseg` in the background when a state loads, for new voices
+    pub mseg: Arc<MsegShape>,
+    /// `TestParams::lfo_shapes` rendered in the background whenever they change, one for each
+    /// LFO set to a custom waveform
+    pub lfo_tables: [Arc<LFOShapeTable>; LFOS],
+    /// planned and allocated in the background rather than by every oscillator of every voice
+    pub spectral_pool: Arc<SpectralPool>,
+}
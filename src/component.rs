@@ -4,4 +4,21 @@ pub mod lfo;
 pub mod noiseosc;
 pub mod oscillator;
 pub mod subosc;
-pub mod params;
\ No newline at end of file
+pub mod params;
+pub mod crossmod;
+pub mod sync;
+pub mod phase_warp;
+pub mod sampler;
+pub mod sfz;
+pub mod additive;
+pub mod pluck;
+pub mod granular;
+pub mod phase_mode;
+pub mod tuning;
+pub mod vector;
+pub mod spectral;
+pub mod fm;
+pub mod analog;
+pub mod mseg;
+pub mod lfo_shape;
+pub mod lfo_random;
\ No newline at end of file
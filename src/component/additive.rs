@@ -0,0 +1,253 @@
+use crate::util::{increment_mod::increment_mod_01_f32, param_range::ParamRange, simple_waveforms::fast_sine};
+
+use super::{params::{ParamSource, ParamPolarity, Param}, oscillator::UnisonSpec, phase_mode::PhaseMode, tuning::{TuningSpec, Tuning}};
+
+pub const MAX_PARTIALS: usize = 64;
+
+pub struct PartialSpec {
+    amp: f32,
+    /// frequency as a multiple of the played note
+    ratio: f32,
+    phase: f32,
+}
+impl PartialSpec {
+    pub fn new(
+        amp: f32,
+        ratio: f32,
+        phase: f32,
+    ) -> Self {
+        Self { amp, ratio, phase }
+    }
+    /// Whole-number harmonics with amplitudes from `amp(k)`, `k` starting at `1`.
+    pub fn harmonic_series(n: usize, amp: impl Fn(usize) -> f32) -> Vec<Self> {
+        (1 ..= n.min(MAX_PARTIALS)).map(|k| Self::new(amp(k), k as f32, 0.0)).collect()
+    }
+}
+
+/// Base values of the controls that act on all partials at once.
+pub struct AdditiveMacroSpec {
+    /// in dB per octave
+    tilt: f32,
+    /// `-1.0` keeps only odd partials, `1.0` only even ones
+    odd_even: f32,
+    /// pushes partials sharp (or flat) the higher they are
+    stretch: f32,
+    /// lowest and highest partial number that is heard, counting from `1`
+    lowest: f32,
+    highest: f32,
+}
+impl AdditiveMacroSpec {
+    pub fn new(
+        tilt: f32,
+        odd_even: f32,
+        stretch: f32,
+        lowest: f32,
+        highest: f32,
+    ) -> Self {
+        Self { tilt, odd_even, stretch, lowest, highest }
+    }
+    pub fn neutral() -> Self {
+        Self::new(0.0, 0.0, 0.0, 1.0, MAX_PARTIALS as f32)
+    }
+}
+
+pub struct AdditiveOscillatorSpec {
+    unison_spec: UnisonSpec,
+    partials: Vec<PartialSpec>,
+    tuning: TuningSpec,
+    macros: AdditiveMacroSpec,
+}
+impl AdditiveOscillatorSpec {
+    pub fn new(
+        unison_spec: UnisonSpec,
+        partials: Vec<PartialSpec>,
+        tuning: TuningSpec,
+        macros: AdditiveMacroSpec,
+    ) -> Self {
+        Self { unison_spec, partials, tuning, macros }
+    }
+}
+
+struct AdditiveUnisonVoice {
+    phases: [f32; MAX_PARTIALS],
+    gain: f32,
+    freq_off: f32,
+}
+
+pub struct AdditiveOscillator {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    voices: Vec<AdditiveUnisonVoice>,
+    n_partials: usize,
+    amps: [f32; MAX_PARTIALS],
+    ratios: [f32; MAX_PARTIALS],
+    /// phase of each partial at the start of a cycle
+    offsets: [f32; MAX_PARTIALS],
+    phase_mode: PhaseMode,
+    /// when the note started on the global clock, in seconds
+    note_time: f64,
+    started: bool,
+
+    pub tuning: Tuning,
+    pub freq: Param,
+    pub tilt: Param,
+    pub odd_even: Param,
+    pub stretch: Param,
+    pub lowest: Param,
+    pub highest: Param,
+}
+
+impl AdditiveOscillator {
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_tilt() -> ParamRange { ParamRange::linear(-12.0, 12.0) }
+    pub fn rangeof_odd_even() -> ParamRange { ParamRange::linear(-1.0, 1.0) }
+    pub fn rangeof_stretch() -> ParamRange { ParamRange::linear(-0.5, 0.5) }
+    pub fn rangeof_partial() -> ParamRange { ParamRange::linear(1.0, MAX_PARTIALS as f32) }
+    pub fn new(sample_rate: f32, spec: AdditiveOscillatorSpec) -> Self {
+        let unison = &spec.unison_spec;
+        let voices = (0 .. unison.n_voices()).map(|i| {
+            let (gain, freq_off) = unison.voice_shape(i);
+            AdditiveUnisonVoice { phases: [0.0; MAX_PARTIALS], gain, freq_off }
+        }).collect();
+
+        let macros = &spec.macros;
+        let mut self_ = Self {
+            sample_rate,
+
+            buffer: vec![],
+            voices,
+            n_partials: 0,
+            amps: [0.0; MAX_PARTIALS],
+            ratios: [0.0; MAX_PARTIALS],
+            offsets: [0.0; MAX_PARTIALS],
+            phase_mode: unison.phase_mode(),
+            note_time: 0.0,
+            started: false,
+
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+            tilt: Param::new(macros.tilt, Self::rangeof_tilt()),
+            odd_even: Param::new(macros.odd_even, Self::rangeof_odd_even()),
+            stretch: Param::new(macros.stretch, Self::rangeof_stretch()),
+            lowest: Param::new(macros.lowest, Self::rangeof_partial()),
+            highest: Param::new(macros.highest, Self::rangeof_partial()),
+        };
+        self_.set_partials(&spec.partials);
+        self_
+    }
+    fn set_partials(&mut self, partials: &[PartialSpec]) {
+        self.n_partials = partials.len().min(MAX_PARTIALS);
+        self.amps = [0.0; MAX_PARTIALS];
+        self.ratios = [0.0; MAX_PARTIALS];
+        self.offsets = [0.0; MAX_PARTIALS];
+        for (k, partial) in partials.iter().take(self.n_partials).enumerate() {
+            self.amps[k] = partial.amp;
+            self.ratios[k] = partial.ratio;
+            self.offsets[k] = partial.phase;
+        }
+    }
+    pub fn update_spec(&mut self, spec: AdditiveOscillatorSpec) {
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.tilt.rebase(spec.macros.tilt);
+        self.odd_even.rebase(spec.macros.odd_even);
+        self.stretch.rebase(spec.macros.stretch);
+        self.lowest.rebase(spec.macros.lowest);
+        self.highest.rebase(spec.macros.highest);
+        self.phase_mode = spec.unison_spec.phase_mode();
+        self.set_partials(&spec.partials);
+
+        // Added voices pick up where the first one is, so they don't start with a click.
+        let unison = &spec.unison_spec;
+        let phases = self.voices.first().map_or([0.0; MAX_PARTIALS], |voice| voice.phases);
+        self.voices.resize_with(unison.n_voices(), || AdditiveUnisonVoice { phases, gain: 0.0, freq_off: 1.0 });
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            (voice.gain, voice.freq_off) = unison.voice_shape(i);
+        }
+    }
+    /// Set when the note started on the global clock, for free-running phase.
+    pub fn set_note_time(&mut self, note_time: f64) {
+        self.note_time = note_time;
+    }
+    /// Render the block, with the macros applied from their values at its start.
+    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+        self.buffer.clear();
+
+        let freq = self.freq.take(block_len);
+        let tilt = self.tilt.take(block_len).first().copied().unwrap_or(0.0);
+        let odd_even = self.odd_even.take(block_len).first().copied().unwrap_or(0.0);
+        let stretch = self.stretch.take(block_len).first().copied().unwrap_or(0.0);
+        let lowest = self.lowest.take(block_len).first().copied().unwrap_or(1.0);
+        let highest = self.highest.take(block_len).first().copied().unwrap_or(MAX_PARTIALS as f32);
+
+        // Apply the macros to every partial, and list the ones left audible.
+        let mut amps = [0.0f32; MAX_PARTIALS];
+        let mut ratios = [0.0f32; MAX_PARTIALS];
+        let mut audible = [0usize; MAX_PARTIALS];
+        let mut n_audible = 0;
+        for k in 0 .. self.n_partials {
+            let number = (k + 1) as f32;
+            let ratio = self.ratios[k];
+            ratios[k] = ratio.max(0.0).powf(1.0 + stretch);
+
+            let tilt_gain = ratio.max(1.0).powf(tilt / 6.0206);
+            let odd_even_gain = if (k + 1) % 2 == 1 {
+                (1.0 - odd_even).min(1.0)
+            } else {
+                (1.0 + odd_even).min(1.0)
+            };
+            let range_gain = (number - lowest + 1.0).clamp(0.0, 1.0)
+                * (highest - number + 1.0).clamp(0.0, 1.0);
+            amps[k] = self.amps[k] * tilt_gain * odd_even_gain * range_gain;
+            if amps[k] != 0.0 {
+                audible[n_audible] = k;
+                n_audible += 1;
+            }
+        }
+
+        let nyquist = self.sample_rate * 0.5;
+        for (i, &freq) in freq.iter().enumerate() {
+            if i == trigger_at && !self.started {
+                self.started = true;
+                for voice in &mut self.voices {
+                    let phase = self.phase_mode.start_phase(freq * voice.freq_off, self.note_time);
+                    for k in 0 .. self.n_partials {
+                        voice.phases[k] = (self.offsets[k] + phase * self.ratios[k]).rem_euclid(1.0);
+                    }
+                }
+            }
+
+            let mut value = 0.0;
+            for voice in &mut self.voices {
+                let voice_freq = freq * voice.freq_off;
+                for &k in &audible[.. n_audible] {
+                    // Partials above nyquist alias, so they are left out.
+                    if voice_freq * ratios[k] < nyquist {
+                        value += fast_sine(voice.phases[k]) * amps[k] * voice.gain;
+                    }
+                }
+                // Silent partials keep running too, so one faded or gliding back in comes back
+                // where it should be.
+                if i >= trigger_at {
+                    let delta = voice_freq / self.sample_rate;
+                    for (phase, ratio) in voice.phases.iter_mut().zip(&ratios).take(self.n_partials) {
+                        increment_mod_01_f32(phase, delta * ratio);
+                    }
+                }
+            }
+            self.buffer.push(value);
+        }
+    }
+    /// Output silence for the block instead of rendering it, for when nothing would hear it.
+    pub fn skip(&mut self, block_len: usize) {
+        self.buffer.clear();
+        self.buffer.resize(block_len, 0.0);
+    }
+}
+impl ParamSource for AdditiveOscillator {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
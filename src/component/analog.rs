@@ -0,0 +1,148 @@
+use crate::util::{param_range::ParamRange, seeded_rng::SeededRng};
+
+use super::{params::{ParamSource, ParamPolarity, Param}, env_adsr::EnvelopeADSR, tuning::Tuning};
+
+/// How many components get their own phase drift, on top of the drift shared by the voice.
+pub const PHASE_DRIFTS: usize = 3;
+
+pub struct AnalogSpec {
+    /// scales every target below, `0.0` makes all voices identical
+    amount: f32,
+    /// largest fixed detune of a voice, in cents
+    pitch_offset: f32,
+    /// largest slow detune of the whole voice, in cents
+    pitch_drift: f32,
+    /// largest slow detune of single components against each other, in cents
+    phase_drift: f32,
+    /// how often the drifts head somewhere new, in Hz
+    drift_rate: f32,
+    /// largest change of envelope times and sustain levels, as a normalized param offset
+    env_time: f32,
+    env_level: f32,
+}
+impl AnalogSpec {
+    pub fn new(
+        amount: f32,
+        pitch_offset: f32,
+        pitch_drift: f32,
+        phase_drift: f32,
+        drift_rate: f32,
+        env_time: f32,
+        env_level: f32,
+    ) -> Self {
+        Self { amount, pitch_offset, pitch_drift, phase_drift, drift_rate, env_time, env_level }
+    }
+    pub fn none() -> Self {
+        Self::new(0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+    }
+}
+
+/// Smoothed random walk between `-1.0` and `1.0`.
+pub struct Drift {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    rng: SeededRng,
+    value: f32,
+    target: f32,
+    /// samples until a new target is picked
+    until_next: f32,
+
+    pub rate: Param,
+}
+impl Drift {
+    pub fn rangeof_rate() -> ParamRange { ParamRange::exponential(0.01, 10.0) }
+    fn new(sample_rate: f32, rate: f32, seed: u64) -> Self {
+        let mut rng = SeededRng::new(seed);
+        let value = rng.next_bipolar();
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            target: value,
+            value,
+            until_next: 0.0,
+            rng,
+
+            rate: Param::new(rate, Self::rangeof_rate()),
+        }
+    }
+    fn block(&mut self, block_len: usize) {
+        self.buffer.clear();
+        let rate = self.rate.take(block_len);
+        for rate in rate.iter() {
+            if self.until_next <= 0.0 {
+                self.target = self.rng.next_bipolar();
+                self.until_next += self.sample_rate / rate;
+            }
+            self.until_next -= 1.0;
+            let k = 1.0 - (-std::f32::consts::TAU * rate / self.sample_rate).exp();
+            self.value += (self.target - self.value) * k;
+            self.buffer.push(self.value);
+        }
+    }
+}
+impl ParamSource for Drift {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
+
+/// Small differences between voices, all drawn from the voice's seed so renders repeat.
+pub struct Analog {
+    rng: SeededRng,
+    /// fixed detune of this voice, in cents
+    pitch_offset: f32,
+    /// depth of `pitch` and `phase`, in cents
+    pitch_drift: f32,
+    phase_drift: f32,
+    env_time: f32,
+    env_level: f32,
+
+    pub pitch: Drift,
+    pub phase: [Drift; PHASE_DRIFTS],
+}
+impl Analog {
+    pub fn new(sample_rate: f32, spec: AnalogSpec, seed: u64) -> Self {
+        let mut rng = SeededRng::new(seed);
+        let amount = spec.amount;
+        Self {
+            pitch_offset: rng.next_bipolar() * spec.pitch_offset * amount,
+            pitch_drift: spec.pitch_drift * amount,
+            phase_drift: spec.phase_drift * amount,
+            env_time: spec.env_time * amount,
+            env_level: spec.env_level * amount,
+
+            pitch: Drift::new(sample_rate, spec.drift_rate, rng.next_u64()),
+            phase: std::array::from_fn(|_| Drift::new(sample_rate, spec.drift_rate, rng.next_u64())),
+            rng,
+        }
+    }
+    /// Detune a component by the voice's fixed offset.
+    pub fn apply_tuning(&self, tuning: &mut Tuning) {
+        let range = Tuning::rangeof_fine();
+        tuning.fine.send_paraminit(range.normalize(self.pitch_offset) - range.normalize(0.0), 1.0);
+    }
+    /// Move an envelope's times and sustain level by this voice's share of variance.
+    pub fn vary_envelope(&mut self, env: &mut EnvelopeADSR) {
+        env.attack.send_paraminit(self.rng.next_bipolar(), self.env_time);
+        env.decay.send_paraminit(self.rng.next_bipolar(), self.env_time);
+        env.release.send_paraminit(self.rng.next_bipolar(), self.env_time);
+        env.sustain.send_paraminit(self.rng.next_bipolar(), self.env_level);
+    }
+    pub fn block(&mut self, block_len: usize) {
+        self.pitch.block(block_len);
+        for drift in &mut self.phase {
+            drift.block(block_len);
+        }
+    }
+    /// Send the voice's pitch drift, and the phase drift of `component` if it has one, to a
+    /// frequency param with an exponential `range`.
+    pub fn send_pitch(&self, freq: &mut Param, range: &ParamRange, component: Option<usize>) {
+        freq.send(&self.pitch, ParamPolarity::Bipolar, range.cents_offset(self.pitch_drift));
+        if let Some(drift) = component.and_then(|i| self.phase.get(i)) {
+            freq.send(drift, ParamPolarity::Bipolar, range.cents_offset(self.phase_drift));
+        }
+    }
+}
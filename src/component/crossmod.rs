@@ -0,0 +1,110 @@
+use super::params::{ParamSource, ParamPolarity, ParamSourceImpl};
+
+/// How far linear FM can push the carrier frequency, as a multiple of itself.
+const FM_RANGE: f32 = 4.0;
+/// How far phase modulation can push the read position, in cycles.
+const PM_RANGE: f32 = 2.0;
+
+#[derive(Clone, Copy)]
+pub enum CrossModMode {
+    None,
+    /// Linear (through-zero) frequency modulation.
+    FM,
+    /// Phase modulation of the wavetable read position.
+    PM,
+    /// Unipolar amplitude modulation.
+    AM,
+    /// Bipolar ring modulation.
+    Ring,
+}
+impl CrossModMode {
+    pub fn apply_freq(&self, freq: f32, m: f32, depth: f32) -> f32 {
+        match self {
+            Self::FM => freq * (1.0 + depth * FM_RANGE * m),
+            _ => freq,
+        }
+    }
+    pub fn apply_phase(&self, phase: f32, m: f32, depth: f32) -> f32 {
+        match self {
+            Self::PM => (phase + depth * PM_RANGE * m).rem_euclid(1.0),
+            _ => phase,
+        }
+    }
+    pub fn apply_amplitude(&self, value: f32, m: f32, depth: f32) -> f32 {
+        match self {
+            Self::AM => value * (1.0 - depth * 0.5 * (1.0 - m)),
+            Self::Ring => value * (1.0 - depth + depth * m),
+            _ => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum CrossModSource {
+    OscP,
+    /// one of the voice's main oscillators, by index
+    Osc(usize),
+    Sub,
+    Noise,
+    Sampler,
+}
+
+pub struct CrossModSpec {
+    mode: CrossModMode,
+    source: CrossModSource,
+    depth: f32,
+}
+impl CrossModSpec {
+    pub fn new(
+        mode: CrossModMode,
+        source: CrossModSource,
+        depth: f32,
+    ) -> Self {
+        Self { mode, source, depth }
+    }
+    pub fn none() -> Self {
+        Self::new(CrossModMode::None, CrossModSource::OscP, 0.0)
+    }
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+}
+
+/// Audio-rate input from another component, consumed sample by sample.
+pub struct CrossModInput {
+    pub mode: CrossModMode,
+    pub source: CrossModSource,
+    buffer: Vec<f32>,
+    sent: bool,
+}
+impl CrossModInput {
+    pub fn new(spec: &CrossModSpec) -> Self {
+        Self {
+            mode: spec.mode,
+            source: spec.source,
+            buffer: vec![],
+            sent: false,
+        }
+    }
+    pub fn update_spec(&mut self, spec: &CrossModSpec) {
+        self.mode = spec.mode;
+        self.source = spec.source;
+    }
+    pub fn is_active(&self) -> bool {
+        !matches!(self.mode, CrossModMode::None)
+    }
+
+    pub fn send<T : ParamSource>(&mut self, source: &T) {
+        self.buffer = source.get_param_buffer(ParamPolarity::Bipolar);
+        self.sent = true;
+    }
+    /// Take the modulator buffer, if one was sent for this block.
+    pub fn take(&mut self, block_len: usize) -> Option<&Vec<f32>> {
+        let sent = std::mem::replace(&mut self.sent, false);
+        if sent && self.is_active() && self.buffer.len() == block_len {
+            Some(&self.buffer)
+        } else {
+            None
+        }
+    }
+}
@@ -2,131 +2,226 @@ use crate::{util::{lx_interp::LXInterp, lerpable::Lerpable, param_range::ParamRa
 
 use super::params::{ParamSource, ParamPolarity, ParamImmut};
 
+/// A timed stage of the envelope and the curve it follows, as the `k` of an `LXInterp`.
+pub struct EnvStage {
+    time: f32,
+    curve: f32,
+}
+impl EnvStage {
+    pub fn new(
+        time: f32,
+        curve: f32,
+    ) -> Self {
+        Self { time, curve }
+    }
+    pub fn linear(time: f32) -> Self {
+        Self::new(time, 0.0)
+    }
+}
+
+/// What the envelope does while the note is held, and when it is played again.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EnvMode {
+    /// attack, hold and decay into the sustain level, then release at note-off
+    Normal,
+    /// attack, hold and decay to zero, repeating while the note is held
+    LoopAD,
+    /// attack, hold and decay to the sustain level, repeating from there while the note is held
+    LoopADS,
+    /// attack, hold and decay to zero, whether the note is released or not
+    OneShot,
+    /// like `Normal`, but a retrigger attacks from the current level instead of from zero
+    Legato,
+}
+
 pub struct ADSRSpec {
-    attack: f32,
-    attack_k: f32,
-    
-    decay: f32,
-    decay_k: f32,
-    
+    delay: f32,
+    attack: EnvStage,
+    hold: f32,
+    decay: EnvStage,
     sustain: f32,
-    
-    release: f32,
-    release_k: f32,
+    release: EnvStage,
+    mode: EnvMode,
 }
 impl ADSRSpec {
+    pub fn new(
+        delay: f32,
+        attack: EnvStage,
+        hold: f32,
+        decay: EnvStage,
+        sustain: f32,
+        release: EnvStage,
+        mode: EnvMode,
+    ) -> Self {
+        Self { delay, attack, hold, decay, sustain, release, mode }
+    }
     pub fn linear(
         attack: f32,
         decay: f32,
         sustain: f32,
         release: f32,
     ) -> Self {
-        Self {
-            attack,
-            decay,
+        Self::new(
+            0.0,
+            EnvStage::linear(attack),
+            0.0,
+            EnvStage::linear(decay),
             sustain,
-            release,
-            attack_k: 0.0,
-            decay_k: 0.0,
-            release_k: 0.0,
-        }
+            EnvStage::linear(release),
+            EnvMode::Normal,
+        )
     }
 }
 
 pub struct EnvelopeADSR {
+    pub delay: ParamImmut,
     pub attack: ParamImmut,
-    attack_interp: LXInterp,
-    
+    pub attack_curve: ParamImmut,
+    pub hold: ParamImmut,
     pub decay: ParamImmut,
-    decay_interp: LXInterp,
-    
+    pub decay_curve: ParamImmut,
     pub sustain: ParamImmut,
-    
     pub release: ParamImmut,
-    release_interp: LXInterp,
-    
+    pub release_curve: ParamImmut,
+    mode: EnvMode,
+
     buffer: Vec<f32>,
-    adsrv: ADSRv,
+    /// output of the last sample
+    level: f32,
+    /// level the attack starts from, which is only above zero after a legato retrigger
+    attack_from: f32,
+    /// level the release started from, once it has
+    release_from: Option<f32>,
 }
 
 impl EnvelopeADSR {
+    pub fn rangeof_delay() -> ParamRange { ParamRange::exponential_to_zero(100.0, 0.001) }
     pub fn rangeof_attack() -> ParamRange { ParamRange::exponential(100.0, 0.001) }
+    pub fn rangeof_hold() -> ParamRange { ParamRange::exponential_to_zero(100.0, 0.001) }
     pub fn rangeof_decay() -> ParamRange { ParamRange::exponential(100.0, 0.001) }
     pub fn rangeof_sustain() -> ParamRange { ParamRange::exponential_to_zero(1.0, 0.01) }
     pub fn rangeof_release() -> ParamRange { ParamRange::exponential(100.0, 0.001) }
+    pub fn rangeof_curve() -> ParamRange { ParamRange::linear(-20.0, 20.0) }
 
     pub fn new(spec: ADSRSpec) -> Self {
-        let attack = ParamImmut::new(spec.attack, Self::rangeof_attack());
-        let decay = ParamImmut::new(spec.decay, Self::rangeof_decay());
-        let sustain = ParamImmut::new(spec.sustain, Self::rangeof_sustain());
-        let release = ParamImmut::new(spec.release, Self::rangeof_release());
         Self {
-            adsrv: ADSRv { attack: attack.read(), decay: decay.read(), sustain: sustain.read(), release: release.read() },
-            buffer: vec![],
+            delay: ParamImmut::new(spec.delay, Self::rangeof_delay()),
+            attack: ParamImmut::new(spec.attack.time, Self::rangeof_attack()),
+            attack_curve: ParamImmut::new(spec.attack.curve, Self::rangeof_curve()),
+            hold: ParamImmut::new(spec.hold, Self::rangeof_hold()),
+            decay: ParamImmut::new(spec.decay.time, Self::rangeof_decay()),
+            decay_curve: ParamImmut::new(spec.decay.curve, Self::rangeof_curve()),
+            sustain: ParamImmut::new(spec.sustain, Self::rangeof_sustain()),
+            release: ParamImmut::new(spec.release.time, Self::rangeof_release()),
+            release_curve: ParamImmut::new(spec.release.curve, Self::rangeof_curve()),
+            mode: spec.mode,
 
-            attack,
-            attack_interp: LXInterp::new(spec.attack_k),
-            decay,
-            decay_interp: LXInterp::new(spec.decay_k),
-            sustain,
-            release,
-            release_interp: LXInterp::new(spec.release_k),
+            buffer: vec![],
+            level: 0.0,
+            attack_from: 0.0,
+            release_from: None,
         }
     }
 
     pub fn update_spec(&mut self, spec: ADSRSpec) {
-        self.attack.rebase(spec.attack);
-        self.attack_interp.set_k(spec.attack_k);
-        self.decay.rebase(spec.decay);
-        self.decay_interp.set_k(spec.decay_k);
+        self.delay.rebase(spec.delay);
+        self.attack.rebase(spec.attack.time);
+        self.attack_curve.rebase(spec.attack.curve);
+        self.hold.rebase(spec.hold);
+        self.decay.rebase(spec.decay.time);
+        self.decay_curve.rebase(spec.decay.curve);
         self.sustain.rebase(spec.sustain);
-        self.release.rebase(spec.release);
-        self.release_interp.set_k(spec.release_k);
-
-        self.adsrv = ADSRv { attack: self.attack.read(), decay: self.decay.read(), sustain: self.sustain.read(), release: self.release.read() };
+        self.release.rebase(spec.release.time);
+        self.release_curve.rebase(spec.release.curve);
+        self.mode = spec.mode;
+    }
+    /// Read the params each time, so per-note offsets sent to them are picked up.
+    fn adsrv(&self) -> ADSRv {
+        ADSRv {
+            delay: self.delay.read(),
+            attack: self.attack.read(),
+            hold: self.hold.read(),
+            decay: self.decay.read(),
+            sustain: self.sustain.read(),
+            release: self.release.read(),
+        }
     }
 
     pub fn begin_block(&mut self) {
         self.buffer.clear();
     }
     pub fn update_block(&mut self, state: &NoteStateCurrentRaw) {
-        self.buffer.push(self.value(state));
+        let value = self.value(state);
+        self.buffer.push(value);
     }
-    
+
     pub fn update_note_ended(&self, state: &mut NoteState) {
-        let ADSRv { attack, decay, sustain, release } = self.adsrv;
-        if state.seconds_since_released() > release ||
-            (sustain == 0.0 && state.seconds_since_triggered() > attack + decay)
-        {
+        let ADSRv { delay, attack, hold, decay, sustain, release } = self.adsrv();
+        let decayed = state.seconds_since_triggered() > delay + attack + hold + decay;
+        let ended = match self.mode {
+            EnvMode::Normal | EnvMode::Legato => {
+                state.seconds_since_released() > release || (sustain == 0.0 && decayed)
+            }
+            // Looping never settles while held, only the release can end it.
+            EnvMode::LoopAD | EnvMode::LoopADS => state.seconds_since_released() > release,
+            EnvMode::OneShot => decayed,
+        };
+        if ended {
             state.mark_ended();
         }
     }
 
-    fn value(&self, state: &NoteStateCurrentRaw) -> f32 {
+    /// Level while the note is held, `since_trigger` seconds in.
+    fn held_value(&self, since_trigger: f32) -> f32 {
+        let ADSRv { delay, attack, hold, decay, sustain, .. } = self.adsrv();
+        let mut t = since_trigger - delay;
+        let mut from = self.attack_from;
+        let floor = match self.mode {
+            EnvMode::Normal | EnvMode::LoopADS | EnvMode::Legato => sustain,
+            EnvMode::LoopAD | EnvMode::OneShot => 0.0,
+        };
+        let cycle = attack + hold + decay;
+        if matches!(self.mode, EnvMode::LoopAD | EnvMode::LoopADS) && t >= cycle && cycle > 0.0 {
+            // Every cycle after the first starts from where the last one ended.
+            t = t.rem_euclid(cycle);
+            from = floor;
+        }
+        if t < 0.0 {
+            from
+        } else if t < attack {
+            LXInterp::new(self.attack_curve.read()).interpolate_unity(t / attack)
+                .lerp(from, 1.0)
+        } else if t < attack + hold {
+            1.0
+        } else if t < cycle {
+            LXInterp::new(self.decay_curve.read()).interpolate_unity((t - attack - hold) / decay)
+                .lerp(1.0, floor)
+        } else {
+            floor
+        }
+    }
+
+    fn value(&mut self, state: &NoteStateCurrentRaw) -> f32 {
         if state.has_ended {
             return 0.0;
         }
-        let ADSRv { attack, decay, sustain, release } = self.adsrv;
-        (
-            if state.since_trigger < 0.0 {
-                0.0
-            } else if state.since_trigger < attack {
-                self.attack_interp.interpolate_unity(state.since_trigger / attack)
-            } else if state.since_trigger < attack + decay {
-                self.decay_interp.interpolate_unity((state.since_trigger - attack) / decay)
-                    .lerp(1.0, sustain)
-            } else {
-                sustain
-            }
-        ) * (
-            if state.since_release < 0.0 {
-                1.0
-            } else if state.since_release < release {
-                1.0 - self.release_interp.interpolate_unity(state.since_release / release)
-            } else {
-                0.0
-            }
-        )
+        if state.retriggered {
+            self.attack_from = if self.mode == EnvMode::Legato { self.level } else { 0.0 };
+            self.release_from = None;
+        }
+        if state.held || self.mode == EnvMode::OneShot {
+            self.level = if state.has_triggered { self.held_value(state.since_trigger) } else { 0.0 };
+            return self.level;
+        }
+        // Fall from wherever the envelope was, even if that was part way through the attack.
+        let from = *self.release_from.get_or_insert(self.level);
+        let release = self.release.read();
+        self.level = if state.since_release < release {
+            from * (1.0 - LXInterp::new(self.release_curve.read()).interpolate_unity(state.since_release / release))
+        } else {
+            0.0
+        };
+        self.level
     }
 }
 impl ParamSource for EnvelopeADSR {
@@ -137,8 +232,10 @@ impl ParamSource for EnvelopeADSR {
 }
 
 struct ADSRv {
+    delay: f32,
     attack: f32,
+    hold: f32,
     decay: f32,
     sustain: f32,
     release: f32,
-}
\ No newline at end of file
+}
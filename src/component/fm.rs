@@ -0,0 +1,312 @@
+use crate::{util::{increment_mod::increment_phase, param_range::ParamRange}, common_data::{CommonDataRef, WAVETABLE_SLOTS}, note::state::NoteStateCurrentRaw};
+
+use super::{
+    params::{ParamSource, ParamPolarity, Param, ParamImmut},
+    env_adsr::{ADSRSpec, EnvelopeADSR},
+    tuning::{TuningSpec, Tuning},
+};
+
+pub const OPERATORS: usize = 4;
+
+/// Phase offset, in cycles, a modulator at full level adds to what it modulates.
+const MAX_MOD_INDEX: f32 = 2.0;
+/// Phase offset, in cycles, an operator at full feedback adds to itself.
+const MAX_FEEDBACK: f32 = 0.25;
+
+/// How the operators modulate each other. Operators are numbered from `0`, and only ever
+/// modulate lower numbered ones, so rendering from the highest down always has modulators ready.
+#[derive(Clone, Copy)]
+pub enum FmAlgorithm {
+    /// `3 > 2 > 1 > 0`
+    Stack,
+    /// `(2 + 3) > 1 > 0`
+    Fork,
+    /// `(1 + (3 > 2)) > 0`
+    Branch,
+    /// `3 > (1 + 2) > 0`
+    Diamond,
+    /// `(1 > 0) + (3 > 2)`
+    Pairs,
+    /// `3 > (0 + 1 + 2)`
+    Spread,
+    /// `0 + 1 + (3 > 2)`
+    Single,
+    /// `0 + 1 + 2 + 3`
+    Additive,
+}
+impl FmAlgorithm {
+    /// For each operator, a bitmask of the operators modulating it.
+    fn modulators(self) -> [u8; OPERATORS] {
+        match self {
+            Self::Stack => [0b0010, 0b0100, 0b1000, 0],
+            Self::Fork => [0b0010, 0b1100, 0, 0],
+            Self::Branch => [0b0110, 0, 0b1000, 0],
+            Self::Diamond => [0b0110, 0b1000, 0b1000, 0],
+            Self::Pairs => [0b0010, 0, 0b1000, 0],
+            Self::Spread => [0b1000, 0b1000, 0b1000, 0],
+            Self::Single => [0, 0, 0b1000, 0],
+            Self::Additive => [0, 0, 0, 0],
+        }
+    }
+    /// Bitmask of the operators that are heard.
+    fn carriers(self) -> u8 {
+        match self {
+            Self::Stack | Self::Fork | Self::Branch | Self::Diamond => 0b0001,
+            Self::Pairs => 0b0101,
+            Self::Spread | Self::Single => 0b0111,
+            Self::Additive => 0b1111,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum FmFrequency {
+    /// multiple of the played note
+    Ratio(f32),
+    /// in Hz, whatever the note
+    Fixed(f32),
+}
+
+#[derive(Clone, Copy)]
+pub enum FmWaveform {
+    Sine,
+    /// A shared wavetable at `slice`.
+    Wavetable { slot: usize, slice: f32 },
+}
+
+pub struct FmOperatorSpec {
+    frequency: FmFrequency,
+    waveform: FmWaveform,
+    /// output level if a carrier, modulation depth if a modulator
+    level: f32,
+    feedback: f32,
+    /// `0.0` ignores velocity, `1.0` scales the level by it
+    velocity_sensitivity: f32,
+    envelope: ADSRSpec,
+}
+impl FmOperatorSpec {
+    pub fn new(
+        frequency: FmFrequency,
+        waveform: FmWaveform,
+        level: f32,
+        feedback: f32,
+        velocity_sensitivity: f32,
+        envelope: ADSRSpec,
+    ) -> Self {
+        Self { frequency, waveform, level, feedback, velocity_sensitivity, envelope }
+    }
+}
+
+pub struct FmSpec {
+    data: CommonDataRef,
+    tuning: TuningSpec,
+    algorithm: FmAlgorithm,
+    operators: [FmOperatorSpec; OPERATORS],
+}
+impl FmSpec {
+    pub fn new(
+        data: CommonDataRef,
+        tuning: TuningSpec,
+        algorithm: FmAlgorithm,
+        operators: [FmOperatorSpec; OPERATORS],
+    ) -> Self {
+        Self { data, tuning, algorithm, operators }
+    }
+}
+
+pub struct FmOperator {
+    fixed: Option<f32>,
+    waveform: FmWaveform,
+    velocity_sensitivity: f32,
+    velocity_gain: f32,
+
+    pub ratio: ParamImmut,
+    pub level: Param,
+    pub feedback: Param,
+    pub envelope: EnvelopeADSR,
+}
+impl FmOperator {
+    pub fn rangeof_ratio() -> ParamRange { ParamRange::exponential(0.125, 32.0) }
+    pub fn rangeof_level() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_feedback() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    fn new(spec: FmOperatorSpec) -> Self {
+        let (ratio, fixed) = Self::split_frequency(spec.frequency);
+        Self {
+            fixed,
+            waveform: spec.waveform,
+            velocity_sensitivity: spec.velocity_sensitivity,
+            velocity_gain: 1.0,
+
+            ratio: ParamImmut::new(ratio, Self::rangeof_ratio()),
+            level: Param::new(spec.level, Self::rangeof_level()),
+            feedback: Param::new(spec.feedback, Self::rangeof_feedback()),
+            envelope: EnvelopeADSR::new(spec.envelope),
+        }
+    }
+    fn update_spec(&mut self, spec: FmOperatorSpec) {
+        let (ratio, fixed) = Self::split_frequency(spec.frequency);
+        self.fixed = fixed;
+        self.ratio.rebase(ratio);
+        self.waveform = spec.waveform;
+        self.velocity_sensitivity = spec.velocity_sensitivity;
+        self.level.rebase(spec.level);
+        self.feedback.rebase(spec.feedback);
+        self.envelope.update_spec(spec.envelope);
+    }
+    fn split_frequency(frequency: FmFrequency) -> (f32, Option<f32>) {
+        match frequency {
+            FmFrequency::Ratio(ratio) => (ratio, None),
+            FmFrequency::Fixed(freq) => (1.0, Some(freq)),
+        }
+    }
+    fn set_velocity(&mut self, velocity: f32) {
+        self.velocity_gain = 1.0 - self.velocity_sensitivity * (1.0 - velocity);
+    }
+}
+
+/// Four operators phase modulating each other along an `FmAlgorithm`.
+pub struct FmEngine {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    data: CommonDataRef,
+    algorithm: FmAlgorithm,
+    started: bool,
+    /// where the next block starts the operators over, as the voice is played again
+    retrigger_at: Option<usize>,
+    phases: [f32; OPERATORS],
+    /// last two outputs of each operator, averaged for feedback to keep it from turning to noise
+    history: [[f32; 2]; OPERATORS],
+
+    pub operators: [FmOperator; OPERATORS],
+    pub tuning: Tuning,
+    pub freq: Param,
+}
+
+impl FmEngine {
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn new(sample_rate: f32, spec: FmSpec) -> Self {
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            data: spec.data,
+            algorithm: spec.algorithm,
+            started: false,
+            retrigger_at: None,
+            phases: [0.0; OPERATORS],
+            history: [[0.0; 2]; OPERATORS],
+
+            operators: spec.operators.map(FmOperator::new),
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+        }
+    }
+    pub fn update_spec(&mut self, spec: FmSpec) {
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.algorithm = spec.algorithm;
+        for (operator, spec) in self.operators.iter_mut().zip(spec.operators) {
+            operator.update_spec(spec);
+        }
+    }
+    /// Scale each operator by the note's velocity, as much as it is sensitive to it.
+    pub fn set_velocity(&mut self, velocity: f32) {
+        for operator in &mut self.operators {
+            operator.set_velocity(velocity);
+        }
+    }
+
+    /// Start the operators over `at` samples into the next block. Their envelopes follow the
+    /// note state on their own.
+    pub fn retrigger(&mut self, at: usize) {
+        self.retrigger_at = Some(at);
+    }
+
+    pub fn begin_block(&mut self) {
+        for operator in &mut self.operators {
+            operator.envelope.begin_block();
+        }
+    }
+    /// Advance the operator envelopes by a sample, alongside the voice's own envelopes.
+    pub fn update_block(&mut self, state: &NoteStateCurrentRaw) {
+        for operator in &mut self.operators {
+            operator.envelope.update_block(state);
+        }
+    }
+
+    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+        self.buffer.clear();
+
+        let uses_tables = self.operators.iter().any(|operator| matches!(operator.waveform, FmWaveform::Wavetable { .. }));
+        let tables = if uses_tables {
+            Some(self.data.lock().unwrap().wavetables.clone())
+        } else {
+            None
+        };
+        let modulators = self.algorithm.modulators();
+        let carriers = self.algorithm.carriers();
+        let carrier_gain = 1.0 / carriers.count_ones() as f32;
+
+        let freq = self.freq.take(block_len);
+        let fixed = self.operators.each_ref().map(|operator| operator.fixed);
+        let ratios = self.operators.each_ref().map(|operator| operator.ratio.read());
+        let waveforms = self.operators.each_ref().map(|operator| operator.waveform);
+        let velocity_gains = self.operators.each_ref().map(|operator| operator.velocity_gain);
+        let [o0, o1, o2, o3] = &mut self.operators;
+        let levels = [o0.level.take(block_len), o1.level.take(block_len), o2.level.take(block_len), o3.level.take(block_len)];
+        let feedbacks = [o0.feedback.take(block_len), o1.feedback.take(block_len), o2.feedback.take(block_len), o3.feedback.take(block_len)];
+        let envelopes = [&o0.envelope, &o1.envelope, &o2.envelope, &o3.envelope].map(|envelope| envelope.source_param_buffer());
+        let retrigger_at = self.retrigger_at.take();
+
+        for i in 0 .. block_len {
+            if (i == trigger_at && !self.started) || Some(i) == retrigger_at {
+                self.started = true;
+                self.phases = [0.0; OPERATORS];
+                self.history = [[0.0; 2]; OPERATORS];
+            }
+
+            let mut outputs = [0.0; OPERATORS];
+            let mut value = 0.0;
+            for k in (0 .. OPERATORS).rev() {
+                let history = &mut self.history[k];
+                let mut phase_mod = (history[0] + history[1]) * 0.5 * feedbacks[k][i] * MAX_FEEDBACK;
+                for (j, output) in outputs.iter().enumerate() {
+                    if modulators[k] & (1 << j) != 0 {
+                        phase_mod += output * MAX_MOD_INDEX;
+                    }
+                }
+                let phase = (self.phases[k] + phase_mod).rem_euclid(1.0);
+                let raw = match (waveforms[k], &tables) {
+                    (FmWaveform::Wavetable { slot, slice }, Some(tables)) => {
+                        tables[slot.min(WAVETABLE_SLOTS - 1)].data.sample(phase, slice)
+                    }
+                    _ => (phase * std::f32::consts::TAU).sin(),
+                };
+                *history = [raw, history[0]];
+                outputs[k] = raw * levels[k][i] * velocity_gains[k] * envelopes[k][i];
+                if carriers & (1 << k) != 0 {
+                    value += outputs[k] * carrier_gain;
+                }
+
+                if i >= trigger_at {
+                    let freq = fixed[k].unwrap_or(freq[i] * ratios[k]);
+                    increment_phase(&mut self.phases[k], self.sample_rate, freq);
+                }
+            }
+            self.buffer.push(value);
+        }
+    }
+    /// Output silence for the block instead of rendering it, for when nothing would hear it.
+    pub fn skip(&mut self, block_len: usize) {
+        self.retrigger_at = None;
+        self.buffer.clear();
+        self.buffer.resize(block_len, 0.0);
+    }
+}
+impl ParamSource for FmEngine {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
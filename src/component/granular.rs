@@ -0,0 +1,244 @@
+use crate::{util::{seeded_rng::SeededRng, param_range::ParamRange}, common_data::CommonDataRef};
+
+use super::{params::{ParamSource, ParamPolarity, Param}, tuning::{TuningSpec, Tuning}};
+
+/// Most grains that can play at once, more are dropped until one finishes.
+pub const MAX_GRAINS: usize = 64;
+
+#[derive(Clone, Copy)]
+pub enum GrainWindow {
+    Hann,
+    Triangle,
+    /// Short fades with a flat middle.
+    Trapezoid,
+    Gaussian,
+}
+impl GrainWindow {
+    /// Gain at `x` through the grain, from `0.0` to `1.0`.
+    fn value(&self, x: f32) -> f32 {
+        match self {
+            Self::Hann => 0.5 - 0.5 * (x * std::f32::consts::TAU).cos(),
+            Self::Triangle => 1.0 - (2.0 * x - 1.0).abs(),
+            Self::Trapezoid => (x.min(1.0 - x) * 10.0).min(1.0),
+            Self::Gaussian => (-18.0 * (x - 0.5) * (x - 0.5)).exp(),
+        }
+    }
+}
+
+pub struct GrainSpec {
+    /// in seconds
+    size: f32,
+    /// grains started per second
+    density: f32,
+    /// random offset to the position, as a fraction of the sample
+    position_jitter: f32,
+    /// random detune, in semitones
+    pitch_jitter: f32,
+    window: GrainWindow,
+}
+impl GrainSpec {
+    pub fn new(
+        size: f32,
+        density: f32,
+        position_jitter: f32,
+        pitch_jitter: f32,
+        window: GrainWindow,
+    ) -> Self {
+        Self { size, density, position_jitter, pitch_jitter, window }
+    }
+}
+
+pub struct GranularSpec {
+    data: CommonDataRef,
+    /// the midi note grains play at their original pitch
+    root_key: u8,
+    tuning: TuningSpec,
+    /// where in the sample grains start, from `0.0` to `1.0`
+    position: f32,
+    grains: GrainSpec,
+    /// how far grains are panned at random
+    spread: f32,
+}
+impl GranularSpec {
+    pub fn new(
+        data: CommonDataRef,
+        root_key: u8,
+        tuning: TuningSpec,
+        position: f32,
+        grains: GrainSpec,
+        spread: f32,
+    ) -> Self {
+        Self { data, root_key, tuning, position, grains, spread }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Grain {
+    active: bool,
+    position: f64,
+    /// samples of source played per output sample
+    rate: f64,
+    age: usize,
+    len: usize,
+    gain_l: f32,
+    gain_r: f32,
+}
+
+pub struct GranularOscillator {
+    sample_rate: f32,
+
+    data: CommonDataRef,
+    buffer: Vec<f32>,
+    buffer_l: Vec<f32>,
+    buffer_r: Vec<f32>,
+    root_freq: f32,
+    window: GrainWindow,
+
+    rng: SeededRng,
+    grains: [Grain; MAX_GRAINS],
+    triggered: bool,
+    /// samples until the next grain starts
+    until_next: f32,
+
+    pub tuning: Tuning,
+    pub freq: Param,
+    pub position: Param,
+    pub size: Param,
+    pub density: Param,
+    pub position_jitter: Param,
+    pub pitch_jitter: Param,
+    pub spread: Param,
+}
+
+impl GranularOscillator {
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_position() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_size() -> ParamRange { ParamRange::exponential(0.005, 1.0) }
+    pub fn rangeof_density() -> ParamRange { ParamRange::exponential(1.0, 500.0) }
+    pub fn rangeof_position_jitter() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_pitch_jitter() -> ParamRange { ParamRange::linear(0.0, 12.0) }
+    pub fn rangeof_spread() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn new(sample_rate: f32, spec: GranularSpec) -> Self {
+        let grains = &spec.grains;
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            buffer_l: vec![],
+            buffer_r: vec![],
+            root_freq: nih_plug::util::midi_note_to_freq(spec.root_key),
+            window: grains.window,
+
+            rng: SeededRng::new(rand::random()),
+            grains: [Grain::default(); MAX_GRAINS],
+            triggered: false,
+            until_next: 0.0,
+
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+            position: Param::new(spec.position, Self::rangeof_position()),
+            size: Param::new(grains.size, Self::rangeof_size()),
+            density: Param::new(grains.density, Self::rangeof_density()),
+            position_jitter: Param::new(grains.position_jitter, Self::rangeof_position_jitter()),
+            pitch_jitter: Param::new(grains.pitch_jitter, Self::rangeof_pitch_jitter()),
+            spread: Param::new(spec.spread, Self::rangeof_spread()),
+
+            data: spec.data,
+        }
+    }
+    pub fn update_spec(&mut self, spec: GranularSpec) {
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.position.rebase(spec.position);
+        self.size.rebase(spec.grains.size);
+        self.density.rebase(spec.grains.density);
+        self.position_jitter.rebase(spec.grains.position_jitter);
+        self.pitch_jitter.rebase(spec.grains.pitch_jitter);
+        self.spread.rebase(spec.spread);
+        self.root_freq = nih_plug::util::midi_note_to_freq(spec.root_key);
+        self.window = spec.grains.window;
+    }
+    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+        self.buffer.clear();
+        self.buffer_l.clear();
+        self.buffer_r.clear();
+
+        let sample = self.data.lock().unwrap().sample.clone();
+        let freq = self.freq.take(block_len);
+        let position = self.position.take(block_len);
+        let size = self.size.take(block_len);
+        let density = self.density.take(block_len);
+        let position_jitter = self.position_jitter.take(block_len);
+        let pitch_jitter = self.pitch_jitter.take(block_len);
+        let spread = self.spread.take(block_len);
+
+        let len = sample.len() as f64;
+        let rate_k = sample.sample_rate / self.sample_rate / self.root_freq;
+        for i in 0 .. block_len {
+            if i == trigger_at {
+                self.triggered = true;
+            }
+            if !self.triggered || len < 2.0 {
+                self.buffer.push(0.0);
+                self.buffer_l.push(0.0);
+                self.buffer_r.push(0.0);
+                continue;
+            }
+
+            self.until_next -= 1.0;
+            while self.until_next <= 0.0 {
+                self.until_next += self.sample_rate / density[i];
+                let grain = match self.grains.iter_mut().find(|grain| !grain.active) {
+                    Some(grain) => grain,
+                    None => continue,
+                };
+                let start = (position[i] + position_jitter[i] * self.rng.next_bipolar()).clamp(0.0, 1.0);
+                let detune = (pitch_jitter[i] * self.rng.next_bipolar() / 12.0).exp2();
+                let pan = spread[i] * self.rng.next_bipolar();
+                *grain = Grain {
+                    active: true,
+                    position: start as f64 * (len - 1.0),
+                    rate: (freq[i] * rate_k * detune) as f64,
+                    age: 0,
+                    len: ((size[i] * self.sample_rate) as usize).max(1),
+                    gain_l: (1.0 - pan).min(1.0),
+                    gain_r: (1.0 + pan).min(1.0),
+                };
+            }
+
+            // Keep the level steady however many grains overlap.
+            let gain = (size[i] * density[i]).max(1.0).sqrt().recip();
+            let (mut l, mut r) = (0.0, 0.0);
+            for grain in self.grains.iter_mut().filter(|grain| grain.active) {
+                let value = sample.read(grain.position)
+                    * self.window.value(grain.age as f32 / grain.len as f32)
+                    * gain;
+                l += value * grain.gain_l;
+                r += value * grain.gain_r;
+                grain.position = (grain.position + grain.rate) % len;
+                grain.age += 1;
+                grain.active = grain.age < grain.len;
+            }
+            self.buffer.push((l + r) * 0.5);
+            self.buffer_l.push(l);
+            self.buffer_r.push(r);
+        }
+    }
+    /// Output silence for the block instead of rendering it, for when nothing would hear it.
+    pub fn skip(&mut self, block_len: usize) {
+        for buffer in [&mut self.buffer, &mut self.buffer_l, &mut self.buffer_r] {
+            buffer.clear();
+            buffer.resize(block_len, 0.0);
+        }
+    }
+    /// Left and right output, after the grains are spread out.
+    pub fn stereo_buffers(&self) -> [&Vec<f32>; 2] {
+        [&self.buffer_l, &self.buffer_r]
+    }
+}
+impl ParamSource for GranularOscillator {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
@@ -1,33 +1,83 @@
-use crate::util::{simple_waveforms::SimpleWaveform, increment_mod::increment_phase, param_range::ParamRange};
+use std::sync::Arc;
 
-use super::params::{ParamSource, ParamPolarity, Param};
+use crate::{util::{simple_waveforms::SimpleWaveform, increment_mod::increment_phase, param_range::ParamRange, note_division::NoteDivision}, common_data::Transport};
 
+use super::{params::{ParamSource, ParamPolarity, Param, ParamImmut}, phase_mode::PhaseMode, lfo_shape::LFOShapeTable, lfo_random::LFORandom};
 
-pub enum LFOPhase {
-    RANDOM,
-    AT(f32),
+/// How many LFOs each voice has, and how many global ones the plugin runs alongside.
+pub const LFOS: usize = 4;
+
+/// Where an LFO runs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LFOScope {
+    /// every voice runs its own
+    Voice,
+    /// one runs for the whole plugin, and every voice reads it
+    Global,
+}
+
+pub enum LFOWaveform {
+    Simple(SimpleWaveform),
+    /// drawn by the user, rendered once and shared by every voice
+    Custom(Arc<LFOShapeTable>),
+    Random(LFORandom),
+}
+impl LFOWaveform {
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self {
+            Self::Simple(waveform) => waveform.sample(phase),
+            Self::Custom(table) => table.sample(phase),
+            Self::Random(random) => random.sample(phase),
+        }
+    }
+    /// Move on by `cycles`, `wrapped` if that started a new one.
+    fn advance(&mut self, cycles: f32, wrapped: bool) {
+        if let Self::Random(random) = self {
+            random.advance(cycles, wrapped);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LFORate {
+    Hz(f32),
+    /// one cycle per `division` at the host tempo, `locked` to the song position while the host
+    /// plays so every playback lines up the same
+    Synced { division: NoteDivision, locked: bool },
 }
-impl LFOPhase {
-    fn get_phase_value(&self) -> f32 {
+impl LFORate {
+    fn freq(&self, tempo: f32) -> f32 {
         match self {
-            Self::RANDOM => rand::random(),
-            Self::AT(phase) => *phase,
+            Self::Hz(freq) => *freq,
+            Self::Synced { division, .. } => division.freq(tempo),
         }
     }
 }
 
 pub struct LFOSpec {
-    freq: f32,
-    phase: LFOPhase,
-    waveform: SimpleWaveform,
+    rate: LFORate,
+    /// `PhaseMode::FreeRunning` turns key retrigger off
+    phase: PhaseMode,
+    waveform: LFOWaveform,
+    scope: LFOScope,
+    /// seconds of silence after the LFO starts
+    delay: f32,
+    /// seconds taken to reach full depth after the delay
+    fade_in: f32,
+    /// stop after one cycle and hold there, like an envelope
+    one_shot: bool,
 }
 impl LFOSpec {
     pub fn new(
-        freq: f32,
-        phase: LFOPhase,
-        waveform: SimpleWaveform,
+        rate: LFORate,
+        phase: PhaseMode,
+        waveform: LFOWaveform,
+        scope: LFOScope,
+        delay: f32,
+        fade_in: f32,
+        one_shot: bool,
     ) -> Self {
-        Self { freq, phase, waveform }
+        Self { rate, phase, waveform, scope, delay, fade_in, one_shot }
     }
 }
 
@@ -37,32 +87,143 @@ pub struct LFO {
     buffer: Vec<f32>,
     spec: LFOSpec,
     phase: f32,
+    /// when the note started on the global clock, in seconds
+    note_time: f64,
+    started: bool,
+    /// sample of this block a global LFO restarts at, for a new note
+    retrigger_at: Option<usize>,
+    /// seconds since the LFO (re)started
+    since_start: f32,
+    /// cycles run since the LFO (re)started
+    cycles: f32,
     pub freq: Param,
+    pub delay: ParamImmut,
+    pub fade_in: ParamImmut,
 }
 
 impl LFO {
     pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.01, 100.0) }
+    pub fn rangeof_delay() -> ParamRange { ParamRange::exponential_to_zero(100.0, 0.001) }
+    pub fn rangeof_fade_in() -> ParamRange { ParamRange::exponential_to_zero(100.0, 0.001) }
     pub fn new(sample_rate: f32, spec: LFOSpec) -> Self {
         Self {
             sample_rate,
             buffer: vec![],
-            freq: Param::new(spec.freq, Self::rangeof_freq()),
-            phase: spec.phase.get_phase_value(),
+            freq: Param::new(spec.rate.freq(Transport::DEFAULT_TEMPO), Self::rangeof_freq()),
+            delay: ParamImmut::new(spec.delay, Self::rangeof_delay()),
+            fade_in: ParamImmut::new(spec.fade_in, Self::rangeof_fade_in()),
+            phase: 0.0,
+            note_time: 0.0,
+            started: false,
+            retrigger_at: None,
+            since_start: 0.0,
+            cycles: 0.0,
             spec,
         }
     }
     pub fn update_spec(&mut self, spec: LFOSpec) {
-        self.freq.rebase(spec.freq);
+        if let LFORate::Hz(freq) = spec.rate {
+            self.freq.rebase(freq);
+        }
+        self.spec.rate = spec.rate;
+        self.delay.rebase(spec.delay);
+        self.fade_in.rebase(spec.fade_in);
+        self.spec.phase = spec.phase;
+        self.spec.waveform = spec.waveform;
+        self.spec.scope = spec.scope;
+        self.spec.one_shot = spec.one_shot;
     }
-    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+    /// Read `table` from now on if this plays a drawn shape, keeping the phase and the rest of
+    /// the spec. The old table is retired by whoever replaced it, so this never frees it.
+    pub fn set_table(&mut self, table: &Arc<LFOShapeTable>) {
+        if let LFOWaveform::Custom(current) = &mut self.spec.waveform {
+            if !Arc::ptr_eq(current, table) {
+                *current = table.clone();
+            }
+        }
+    }
+    pub fn scope(&self) -> LFOScope {
+        self.spec.scope
+    }
+    /// Set when the note started on the global clock, for free-running phase.
+    pub fn set_note_time(&mut self, note_time: f64) {
+        self.note_time = note_time;
+    }
+    /// Restart a global LFO at sample `at` of the next block, unless it is free-running.
+    pub fn retrigger(&mut self, at: usize) {
+        if self.spec.phase.retriggers() && self.retrigger_at.is_none() {
+            self.retrigger_at = Some(at);
+        }
+    }
+    /// Depth the LFO has faded in to, `since_start` seconds in.
+    fn fade(since_start: f32, delay: f32, fade_in: f32) -> f32 {
+        let t = since_start - delay;
+        if t < 0.0 {
+            0.0
+        } else if t < fade_in {
+            t / fade_in
+        } else {
+            1.0
+        }
+    }
+    pub fn block(&mut self, trigger_at: usize, block_len: usize, transport: &Transport) {
         self.buffer.clear();
+        if let LFORate::Synced { division, .. } = self.spec.rate {
+            self.freq.rebase(division.freq(transport.tempo));
+        }
+        // Song position at the start of the block and length of a cycle, both in beats.
+        let locked_to = match (self.spec.rate, transport.position) {
+            (LFORate::Synced { division, locked: true }, Some(position)) => Some((position, division.beats() as f64)),
+            _ => None,
+        };
+        let beats_per_sample = transport.tempo as f64 / 60.0 / self.sample_rate as f64;
+        let song_phase = |i: usize| locked_to.map(|(position, beats)| {
+            ((position + i as f64 * beats_per_sample) / beats).rem_euclid(1.0) as f32 % 1.0
+        });
         let freq = self.freq.take(block_len);
+        let delay = self.delay.read();
+        let fade_in = self.fade_in.read();
         for i in 0 .. block_len {
-            self.buffer.push(self.spec.waveform.sample(self.phase));
-            if i >= trigger_at {
-                increment_phase(&mut self.phase, self.sample_rate, freq[i]);
+            if (i == trigger_at && !self.started) || self.retrigger_at == Some(i) {
+                self.started = true;
+                self.phase = song_phase(i)
+                    .unwrap_or_else(|| self.spec.phase.start_phase(freq[i], self.note_time));
+                self.since_start = 0.0;
+                self.cycles = 0.0;
+            }
+            self.buffer.push(self.spec.waveform.sample(self.phase) * Self::fade(self.since_start, delay, fade_in));
+            if self.started {
+                self.since_start += 1.0 / self.sample_rate;
+                let finished = self.spec.one_shot && self.cycles >= 1.0;
+                if self.since_start > delay && !finished {
+                    let cycle = freq[i] / self.sample_rate;
+                    if self.spec.one_shot && self.cycles + cycle >= 1.0 {
+                        // Hold where the cycle ends, rather than wrapping back to its start.
+                        self.cycles = 1.0;
+                    } else {
+                        let wrapped = match song_phase(i + 1) {
+                            Some(phase) => {
+                                let wrapped = phase < self.phase;
+                                self.phase = phase;
+                                wrapped
+                            }
+                            None => increment_phase(&mut self.phase, self.sample_rate, freq[i]),
+                        };
+                        self.cycles += cycle;
+                        self.spec.waveform.advance(cycle, wrapped);
+                    }
+                }
             }
         }
+        self.retrigger_at = None;
+    }
+    /// Take this block from a global LFO running at the host rate, holding each of its samples
+    /// for the `oversampling` samples this voice runs for it.
+    pub fn follow(&mut self, global: &LFO, oversampling: usize) {
+        self.buffer.clear();
+        for value in &global.buffer {
+            self.buffer.extend(std::iter::repeat_n(*value, oversampling));
+        }
     }
 }
 impl ParamSource for LFO {
@@ -70,4 +231,4 @@ impl ParamSource for LFO {
     fn source_param_buffer(&self) -> &Vec<f32> {
         &self.buffer
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,89 @@
+use crate::util::{seeded_rng::SeededRng, lerpable::Lerpable};
+
+use super::noiseosc::NoiseSeed;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LFORandomKind {
+    /// a new random level every cycle, held until the next
+    SampleHold,
+    /// a new random level every cycle, glided to over the cycle
+    Smooth,
+    /// a random step up or down every cycle, glided to over the cycle
+    Drunk,
+    /// the x axis of a Lorenz attractor, running one time unit per cycle
+    Lorenz,
+}
+
+/// Random LFO waveform, which unlike the others keeps state from cycle to cycle.
+pub struct LFORandom {
+    kind: LFORandomKind,
+    rng: SeededRng,
+    /// levels at the start of this cycle and the next
+    from: f32,
+    to: f32,
+    lorenz: [f32; 3],
+}
+impl LFORandom {
+    /// Largest step of `Drunk` per cycle.
+    const DRUNK_STEP: f32 = 0.25;
+    /// Longest Lorenz step taken at once, past which it stops being stable.
+    const LORENZ_MAX_DT: f32 = 0.01;
+
+    pub fn new(kind: LFORandomKind, seed: NoiseSeed) -> Self {
+        let mut rng = SeededRng::new(seed.value());
+        let from = rng.next_bipolar();
+        let to = match kind {
+            LFORandomKind::Drunk => Self::drunk_step(&mut rng, from),
+            _ => rng.next_bipolar(),
+        };
+        let lorenz = [rng.next_bipolar() * 10.0, rng.next_bipolar() * 10.0, 25.0 + rng.next_bipolar() * 5.0];
+        Self { kind, rng, from, to, lorenz }
+    }
+    /// Step from `level`, bouncing off the edges so it stays in range.
+    fn drunk_step(rng: &mut SeededRng, level: f32) -> f32 {
+        let next = level + rng.next_bipolar() * Self::DRUNK_STEP;
+        if next > 1.0 {
+            2.0 - next
+        } else if next < -1.0 {
+            -2.0 - next
+        } else {
+            next
+        }
+    }
+    /// Move on by `cycles`, `wrapped` if that started a new one.
+    pub fn advance(&mut self, cycles: f32, wrapped: bool) {
+        match self.kind {
+            LFORandomKind::SampleHold | LFORandomKind::Smooth => {
+                if wrapped {
+                    self.from = self.to;
+                    self.to = self.rng.next_bipolar();
+                }
+            }
+            LFORandomKind::Drunk => {
+                if wrapped {
+                    self.from = self.to;
+                    self.to = Self::drunk_step(&mut self.rng, self.to);
+                }
+            }
+            LFORandomKind::Lorenz => {
+                let dt = cycles.min(Self::LORENZ_MAX_DT);
+                let [x, y, z] = self.lorenz;
+                self.lorenz = [
+                    x + 10.0 * (y - x) * dt,
+                    y + (x * (28.0 - z) - y) * dt,
+                    z + (x * y - 8.0 / 3.0 * z) * dt,
+                ];
+            }
+        }
+    }
+    pub fn sample(&self, phase: f32) -> f32 {
+        match self.kind {
+            LFORandomKind::SampleHold => self.from,
+            LFORandomKind::Smooth => {
+                ((1.0 - (phase * std::f32::consts::PI).cos()) / 2.0).lerp(self.from, self.to)
+            }
+            LFORandomKind::Drunk => phase.lerp(self.from, self.to),
+            LFORandomKind::Lorenz => (self.lorenz[0] / 20.0).clamp(-1.0, 1.0),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::util::{lx_interp::LXInterp, lerpable::Lerpable};
+
+use super::lfo::LFOS;
+
+/// A breakpoint of a drawn LFO shape.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LFOPoint {
+    /// position in the cycle, from `0.0` to `1.0`
+    pub x: f32,
+    /// from `-1.0` to `1.0`
+    pub y: f32,
+    /// the `k` of the `LXInterp` the segment leading up to this point follows
+    pub curve: f32,
+}
+impl LFOPoint {
+    pub fn new(
+        x: f32,
+        y: f32,
+        curve: f32,
+    ) -> Self {
+        Self { x, y, curve }
+    }
+}
+
+/// One cycle of an LFO drawn as breakpoints, as saved in the plugin state. The segment after the
+/// last point wraps around to the first.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LFOShape {
+    /// kept sorted by `x`, two points at the same `x` make a step
+    pub points: Vec<LFOPoint>,
+}
+impl Default for LFOShape {
+    fn default() -> Self {
+        LFOShapePreset::Triangle.shape()
+    }
+}
+impl LFOShape {
+    pub fn new(mut points: Vec<LFOPoint>) -> Self {
+        points.sort_by(|a, b| a.x.total_cmp(&b.x));
+        Self { points }
+    }
+    /// Value at `phase`, walking the points. Use `LFOShapeTable` to read it every sample.
+    pub fn value(&self, phase: f32) -> f32 {
+        let (first, last) = match (self.points.first(), self.points.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        // Points at `phase` end the segment, so a step reads the level it is coming from.
+        let next = self.points.iter().position(|point| point.x >= phase);
+        let (from, to, from_x, to_x) = match next {
+            Some(0) => (last, first, last.x - 1.0, first.x),
+            Some(i) => (&self.points[i - 1], &self.points[i], self.points[i - 1].x, self.points[i].x),
+            None => (last, first, last.x, first.x + 1.0),
+        };
+        let width = to_x - from_x;
+        if width <= 0.0 {
+            return to.y;
+        }
+        LXInterp::new(to.curve).interpolate_unity((phase - from_x) / width).lerp(from.y, to.y)
+    }
+}
+
+/// Shapes to start drawing from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LFOShapePreset {
+    RampUp,
+    RampDown,
+    Triangle,
+    Decay,
+    Steps4,
+    Steps8,
+    TripletGate,
+}
+impl LFOShapePreset {
+    pub const ALL: [Self; 7] = [
+        Self::RampUp,
+        Self::RampDown,
+        Self::Triangle,
+        Self::Decay,
+        Self::Steps4,
+        Self::Steps8,
+        Self::TripletGate,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RampUp => "Ramp Up",
+            Self::RampDown => "Ramp Down",
+            Self::Triangle => "Triangle",
+            Self::Decay => "Decay",
+            Self::Steps4 => "4 Steps",
+            Self::Steps8 => "8 Steps",
+            Self::TripletGate => "Triplet Gate",
+        }
+    }
+    pub fn shape(&self) -> LFOShape {
+        match self {
+            Self::RampUp => LFOShape::new(vec![
+                LFOPoint::new(0.0, -1.0, 0.0),
+                LFOPoint::new(1.0, 1.0, 0.0),
+            ]),
+            Self::RampDown => LFOShape::new(vec![
+                LFOPoint::new(0.0, 1.0, 0.0),
+                LFOPoint::new(1.0, -1.0, 0.0),
+            ]),
+            Self::Triangle => LFOShape::new(vec![
+                LFOPoint::new(0.0, -1.0, 0.0),
+                LFOPoint::new(0.5, 1.0, 0.0),
+                LFOPoint::new(1.0, -1.0, 0.0),
+            ]),
+            Self::Decay => LFOShape::new(vec![
+                LFOPoint::new(0.0, 1.0, 0.0),
+                LFOPoint::new(1.0, -1.0, 8.0),
+            ]),
+            Self::Steps4 => Self::steps(&[-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0]),
+            Self::Steps8 => Self::steps(&[-1.0, 0.5, -0.25, 1.0, -0.75, 0.25, -0.5, 0.75]),
+            Self::TripletGate => Self::steps(&[1.0, -1.0, 1.0, -1.0, 1.0, -1.0]),
+        }
+    }
+    /// Equal steps through `levels`, one per fraction of the cycle.
+    fn steps(levels: &[f32]) -> LFOShape {
+        let width = 1.0 / levels.len() as f32;
+        LFOShape::new(levels.iter().enumerate()
+            .flat_map(|(i, level)| [
+                LFOPoint::new(i as f32 * width, *level, 0.0),
+                LFOPoint::new((i + 1) as f32 * width, *level, 0.0),
+            ])
+            .collect())
+    }
+}
+
+/// An `LFOShape` rendered ahead of time, so reading it is a lookup instead of a search.
+pub struct LFOShapeTable {
+    table: Vec<f32>,
+}
+impl LFOShapeTable {
+    const LEN: usize = 1024;
+    pub fn new(shape: &LFOShape) -> Self {
+        Self {
+            table: (0 ..= Self::LEN)
+                .map(|i| shape.value(i as f32 / Self::LEN as f32))
+                .collect(),
+        }
+    }
+    /// Render every LFO's shape, to be shared by all the voices.
+    pub fn new_all(shapes: &[LFOShape; LFOS]) -> [Arc<Self>; LFOS] {
+        std::array::from_fn(|i| Arc::new(Self::new(&shapes[i])))
+    }
+    pub fn sample(&self, phase: f32) -> f32 {
+        let x = phase.clamp(0.0, 1.0) * Self::LEN as f32;
+        let i = (x as usize).min(Self::LEN - 1);
+        (x - i as f32).lerp(self.table[i], self.table[i + 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_stay_in_range() {
+        for preset in LFOShapePreset::ALL {
+            let table = LFOShapeTable::new(&preset.shape());
+            for i in 0 ..= 100 {
+                let value = table.sample(i as f32 / 100.0);
+                assert!((-1.0 ..= 1.0).contains(&value), "{} at {i}: {value}", preset.name());
+            }
+        }
+    }
+
+    #[test]
+    fn steps_hold_their_level() {
+        let shape = LFOShapePreset::Steps4.shape();
+        assert_eq!(shape.value(0.1), -1.0);
+        assert_eq!(shape.value(0.6), 1.0 / 3.0);
+        assert_eq!(shape.value(0.9), 1.0);
+    }
+
+    #[test]
+    fn wraps_from_last_point_to_first() {
+        let shape = LFOShape::new(vec![
+            LFOPoint::new(0.25, 1.0, 0.0),
+            LFOPoint::new(0.75, -1.0, 0.0),
+        ]);
+        assert!(shape.value(0.0).abs() < 1e-6);
+        assert!((shape.value(0.5) - 0.0).abs() < 1e-6);
+        assert!((shape.value(0.875) + 0.5).abs() < 1e-6);
+    }
+}
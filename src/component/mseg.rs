@@ -0,0 +1,197 @@
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{util::{lx_interp::LXInterp, lerpable::Lerpable}, note::state::NoteStateCurrentRaw};
+
+use super::params::{ParamSource, ParamPolarity};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MsegSegment {
+    /// in seconds, or in beats if the shape is tempo synced
+    pub duration: f32,
+    /// level at the end of the segment
+    pub level: f32,
+    /// the `k` of the `LXInterp` the segment follows
+    pub curve: f32,
+}
+impl MsegSegment {
+    pub fn new(
+        duration: f32,
+        level: f32,
+        curve: f32,
+    ) -> Self {
+        Self { duration, level, curve }
+    }
+}
+
+/// Breakpoints of a multi-segment envelope, as saved in the plugin state.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MsegShape {
+    pub start: f32,
+    pub segments: Vec<MsegSegment>,
+    /// segment whose end level is held until release
+    pub sustain: Option<usize>,
+    /// first and last segment repeated until release, taking over from `sustain`
+    pub loop_region: Option<(usize, usize)>,
+    pub tempo_sync: bool,
+}
+impl Default for MsegShape {
+    fn default() -> Self {
+        Self {
+            start: 0.0,
+            segments: vec![
+                MsegSegment::new(0.01, 1.0, 0.0),
+                MsegSegment::new(0.5, 0.3, 4.0),
+                MsegSegment::new(0.5, 0.0, 4.0),
+            ],
+            sustain: Some(1),
+            loop_region: None,
+            tempo_sync: false,
+        }
+    }
+}
+impl MsegShape {
+    /// Segment the shape goes to on release, if holding or looping keeps it from getting there.
+    fn release_segment(&self) -> Option<usize> {
+        match (self.loop_region, self.sustain) {
+            (Some((_, end)), _) | (None, Some(end)) => Some(end + 1),
+            (None, None) => None,
+        }
+    }
+}
+
+pub struct MsegSpec {
+    shape: Arc<MsegShape>,
+    /// used for tempo synced shapes
+    tempo: f32,
+}
+impl MsegSpec {
+    pub fn new(
+        shape: Arc<MsegShape>,
+        tempo: f32,
+    ) -> Self {
+        Self { shape, tempo }
+    }
+}
+
+/// Envelope made of any number of curved segments, following the note from its trigger.
+pub struct Mseg {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    shape: Arc<MsegShape>,
+    /// seconds per unit of segment duration
+    time_scale: f32,
+
+    segment: usize,
+    /// seconds into `segment`
+    segment_time: f32,
+    /// level `segment` starts from
+    from: f32,
+    level: f32,
+    released: bool,
+}
+
+impl Mseg {
+    pub fn new(sample_rate: f32, spec: MsegSpec) -> Self {
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            time_scale: Self::time_scale(&spec),
+
+            segment: 0,
+            segment_time: 0.0,
+            from: spec.shape.start,
+            level: spec.shape.start,
+            released: false,
+
+            shape: spec.shape,
+        }
+    }
+    pub fn update_spec(&mut self, spec: MsegSpec) {
+        self.time_scale = Self::time_scale(&spec);
+        self.shape = spec.shape;
+    }
+    fn time_scale(spec: &MsegSpec) -> f32 {
+        if spec.shape.tempo_sync {
+            60.0 / spec.tempo.max(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    pub fn begin_block(&mut self) {
+        self.buffer.clear();
+    }
+    pub fn update_block(&mut self, state: &NoteStateCurrentRaw) {
+        if state.retriggered {
+            // Start the shape over from wherever it is, so it doesn't jump.
+            self.segment = 0;
+            self.segment_time = 0.0;
+            self.from = self.level;
+            self.released = false;
+        }
+        if state.has_triggered && !state.has_ended {
+            if !state.held && !self.released {
+                self.release();
+            }
+            self.advance(1.0 / self.sample_rate);
+        }
+        self.buffer.push(self.level);
+    }
+
+    /// Leave the sustain point or loop, and go on from the current level.
+    fn release(&mut self) {
+        self.released = true;
+        if let Some(segment) = self.shape.release_segment() {
+            if self.segment <= segment {
+                self.segment = segment;
+                self.segment_time = 0.0;
+                self.from = self.level;
+            }
+        }
+    }
+
+    fn advance(&mut self, dt: f32) {
+        self.segment_time += dt;
+        // Bounded, so a loop of zero length segments can't hang the audio thread.
+        for _ in 0 ..= self.shape.segments.len() {
+            let segment = match self.shape.segments.get(self.segment) {
+                Some(segment) => segment,
+                // Past the last segment, hold where it ended.
+                None => return,
+            };
+            let duration = segment.duration * self.time_scale;
+            if self.segment_time < duration {
+                let x = self.segment_time / duration;
+                self.level = LXInterp::new(segment.curve).interpolate_unity(x).lerp(self.from, segment.level);
+                return;
+            }
+            self.level = segment.level;
+            if !self.released {
+                if let Some((start, end)) = self.shape.loop_region {
+                    if self.segment == end && start <= end {
+                        self.segment_time -= duration;
+                        self.segment = start;
+                        self.from = segment.level;
+                        continue;
+                    }
+                } else if self.shape.sustain == Some(self.segment) {
+                    self.segment_time = duration;
+                    return;
+                }
+            }
+            self.segment_time -= duration;
+            self.segment += 1;
+            self.from = segment.level;
+        }
+    }
+}
+impl ParamSource for Mseg {
+    const POLARITY: ParamPolarity = ParamPolarity::Monopolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
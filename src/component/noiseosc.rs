@@ -1,7 +1,10 @@
-use super::params::{ParamSource, ParamPolarity};
+use crate::util::{seeded_rng::SeededRng, increment_mod::increment_phase, param_range::ParamRange};
+
+use super::params::{ParamSource, ParamPolarity, Param};
 
 
 pub struct MultichunkWhiteNoiseGen {
+    rng: SeededRng,
     buffers: Vec<[f32; Self::L]>,
     sample_i: usize,
     chunk_i: usize,
@@ -9,62 +12,270 @@ pub struct MultichunkWhiteNoiseGen {
 impl MultichunkWhiteNoiseGen {
     const L: usize = 512;
     const SL: usize = 128;
-    pub fn new() -> Self {        
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SeededRng::new(seed);
         let mut buffers = vec![];
         for _ in 0 .. Self::SL {
-            buffers.push(std::array::from_fn(|_| rand::random::<f32>() * 2.0 - 1.0));
+            buffers.push(std::array::from_fn(|_| rng.next_bipolar()));
         }
+        let chunk_i = Self::gen_chunk_i(&mut rng);
         Self {
+            rng,
             buffers,
             sample_i: 0,
-            chunk_i: Self::gen_chunk_i(),
+            chunk_i,
         }
     }
-    fn gen_chunk_i() -> usize {
-        (rand::random::<f32>() * Self::SL as f32) as usize
+    fn gen_chunk_i(rng: &mut SeededRng) -> usize {
+        (rng.next_f32() * Self::SL as f32) as usize
     }
     pub fn sample(&mut self) -> f32 {
         self.sample_i += 1;
         if self.sample_i >= Self::L {
             self.sample_i = 0;
-            self.chunk_i = Self::gen_chunk_i();
+            self.chunk_i = Self::gen_chunk_i(&mut self.rng);
         }
         self.buffers[self.chunk_i][self.sample_i]
     }
 }
 
+/// White noise through Paul Kellet's -3dB/octave filter.
+pub struct PinkNoiseGen {
+    rng: SeededRng,
+    b: [f32; 7],
+}
+impl PinkNoiseGen {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: SeededRng::new(seed), b: [0.0; 7] }
+    }
+    pub fn sample(&mut self) -> f32 {
+        let white = self.rng.next_bipolar();
+        let b = &mut self.b;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.1538520;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * 0.11
+    }
+}
+
+/// Leaky integrated white noise, -6dB/octave.
+pub struct BrownNoiseGen {
+    rng: SeededRng,
+    last: f32,
+}
+impl BrownNoiseGen {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: SeededRng::new(seed), last: 0.0 }
+    }
+    pub fn sample(&mut self) -> f32 {
+        self.last = (self.last + 0.02 * self.rng.next_bipolar()) / 1.02;
+        self.last * 3.5
+    }
+}
+
+/// Differentiated pink noise, +3dB/octave.
+pub struct BlueNoiseGen {
+    pink: PinkNoiseGen,
+    last: f32,
+}
+impl BlueNoiseGen {
+    pub fn new(seed: u64) -> Self {
+        Self { pink: PinkNoiseGen::new(seed), last: 0.0 }
+    }
+    pub fn sample(&mut self) -> f32 {
+        let pink = self.pink.sample();
+        let blue = pink - self.last;
+        self.last = pink;
+        blue * 0.7
+    }
+}
+
+/// Sparse impulses of random sign, one at a random position in every period.
+pub struct VelvetNoiseGen {
+    rng: SeededRng,
+    period: usize,
+    sample_i: usize,
+    impulse_i: usize,
+    impulse: f32,
+}
+impl VelvetNoiseGen {
+    /// impulses per second
+    const DENSITY: f32 = 2000.0;
+    pub fn new(sample_rate: f32, seed: u64) -> Self {
+        let mut gen = Self {
+            rng: SeededRng::new(seed),
+            period: ((sample_rate / Self::DENSITY) as usize).max(1),
+            sample_i: 0,
+            impulse_i: 0,
+            impulse: 0.0,
+        };
+        gen.next_period();
+        gen
+    }
+    fn next_period(&mut self) {
+        self.sample_i = 0;
+        self.impulse_i = (self.rng.next_f32() * self.period as f32) as usize;
+        self.impulse = if self.rng.next_f32() < 0.5 { -1.0 } else { 1.0 };
+    }
+    pub fn sample(&mut self) -> f32 {
+        let value = if self.sample_i == self.impulse_i { self.impulse } else { 0.0 };
+        self.sample_i += 1;
+        if self.sample_i >= self.period {
+            self.next_period();
+        }
+        value
+    }
+}
+
+/// White noise held for a cycle at a time.
+pub struct SampleAndHoldNoiseGen {
+    rng: SeededRng,
+    sample_rate: f32,
+    phase: f32,
+    value: f32,
+}
+impl SampleAndHoldNoiseGen {
+    pub fn new(sample_rate: f32, seed: u64) -> Self {
+        let mut rng = SeededRng::new(seed);
+        Self {
+            value: rng.next_bipolar(),
+            rng,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+    pub fn sample(&mut self, freq: f32) -> f32 {
+        if increment_phase(&mut self.phase, self.sample_rate, freq) {
+            self.value = self.rng.next_bipolar();
+        }
+        self.value
+    }
+}
+
 pub enum NoiseType {
+    MultichunkWhiteNoise,
+    Pink,
+    Brown,
+    Blue,
+    Velvet,
+    /// Keytracked, through the noise oscillator's `freq`.
+    SampleAndHold,
+}
+impl NoiseType {
+    fn into_gen(&self, sample_rate: f32, seed: u64) -> NoiseGen {
+        match self {
+            Self::MultichunkWhiteNoise => NoiseGen::MultichunkWhiteNoise(MultichunkWhiteNoiseGen::new(seed)),
+            Self::Pink => NoiseGen::Pink(PinkNoiseGen::new(seed)),
+            Self::Brown => NoiseGen::Brown(BrownNoiseGen::new(seed)),
+            Self::Blue => NoiseGen::Blue(BlueNoiseGen::new(seed)),
+            Self::Velvet => NoiseGen::Velvet(VelvetNoiseGen::new(sample_rate, seed)),
+            Self::SampleAndHold => NoiseGen::SampleAndHold(SampleAndHoldNoiseGen::new(sample_rate, seed)),
+        }
+    }
+}
+
+enum NoiseGen {
     MultichunkWhiteNoise(MultichunkWhiteNoiseGen),
+    Pink(PinkNoiseGen),
+    Brown(BrownNoiseGen),
+    Blue(BlueNoiseGen),
+    Velvet(VelvetNoiseGen),
+    SampleAndHold(SampleAndHoldNoiseGen),
+}
+impl NoiseGen {
+    fn sample(&mut self, freq: f32) -> f32 {
+        match self {
+            Self::MultichunkWhiteNoise(gen) => gen.sample(),
+            Self::Pink(gen) => gen.sample(),
+            Self::Brown(gen) => gen.sample(),
+            Self::Blue(gen) => gen.sample(),
+            Self::Velvet(gen) => gen.sample(),
+            Self::SampleAndHold(gen) => gen.sample(freq),
+        }
+    }
+}
+
+pub enum NoiseSeed {
+    Random,
+    Fixed(u64),
+}
+impl NoiseSeed {
+    pub fn value(&self) -> u64 {
+        match self {
+            Self::Random => rand::random(),
+            Self::Fixed(seed) => *seed,
+        }
+    }
 }
 
 pub struct NoiseOscillatorSpec {
     noise_type: NoiseType,
+    seed: NoiseSeed,
+    /// give the right channel its own, decorrelated generator
+    stereo: bool,
+    freq_off: f32,
 }
 impl NoiseOscillatorSpec {
     pub fn new(
         noise_type: NoiseType,
+        seed: NoiseSeed,
+        stereo: bool,
+        freq_off: f32,
     ) -> Self {
-        Self { noise_type }
+        Self { noise_type, seed, stereo, freq_off }
     }
 }
 
 pub struct NoiseOscillator {
-    spec: NoiseOscillatorSpec,
+    gen: NoiseGen,
+    gen_r: Option<NoiseGen>,
     buffer: Vec<f32>,
+    buffer_r: Vec<f32>,
+
+    pub freq: Param,
 }
 impl NoiseOscillator {
-    pub fn new(spec: NoiseOscillatorSpec) -> Self {
+    /// Mixed into the seed of the right channel's generator.
+    const STEREO_SEED: u64 = 0xD1B5_4A32_D192_ED03;
+
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn new(sample_rate: f32, spec: NoiseOscillatorSpec) -> Self {
+        let seed = spec.seed.value();
         Self {
-            spec,
+            gen: spec.noise_type.into_gen(sample_rate, seed),
+            gen_r: if spec.stereo {
+                Some(spec.noise_type.into_gen(sample_rate, seed ^ Self::STEREO_SEED))
+            } else {
+                None
+            },
             buffer: vec![],
+            buffer_r: vec![],
+
+            freq: Param::new(spec.freq_off, Self::rangeof_freq()),
         }
     }
     pub fn block(&mut self, _trigger_at: usize, block_len: usize) {
         self.buffer.clear();
-        for _ in 0 .. block_len {
-            self.buffer.push(match &mut self.spec.noise_type {
-                NoiseType::MultichunkWhiteNoise(gen) => gen.sample(),
-            });
+        self.buffer_r.clear();
+        let freq = self.freq.take(block_len);
+        for i in 0 .. block_len {
+            self.buffer.push(self.gen.sample(freq[i]));
+            if let Some(gen_r) = &mut self.gen_r {
+                self.buffer_r.push(gen_r.sample(freq[i]));
+            }
+        }
+    }
+    /// Left and right output, which are the same buffer unless the spec asked for stereo.
+    pub fn stereo_buffers(&self) -> [&Vec<f32>; 2] {
+        if self.gen_r.is_some() {
+            [&self.buffer, &self.buffer_r]
+        } else {
+            [&self.buffer, &self.buffer]
         }
     }
 }
@@ -73,4 +284,4 @@ impl ParamSource for NoiseOscillator {
     fn source_param_buffer(&self) -> &Vec<f32> {
         &self.buffer
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,16 @@
-use crate::{util::{increment_mod::increment_phase, param_range::ParamRange}, common_data::CommonDataRef};
+use crate::{util::{increment_mod::increment_phase, param_range::ParamRange}, common_data::{CommonDataRef, WAVETABLE_SLOTS}};
 
-use super::params::{ParamSource, ParamPolarity, Param};
+use super::{
+    params::{ParamSource, ParamPolarity, Param},
+    crossmod::{CrossModSpec, CrossModInput, CrossModSource},
+    sync::{SyncSpec, SyncInput, SyncMaster, SyncMode, SyncSource, polyblep_before, polyblep_after},
+    phase_warp::{PhaseWarpSpec, PhaseWarpMode},
+    phase_mode::PhaseMode,
+    subosc::PhaseMaster,
+    tuning::{TuningSpec, Tuning},
+    vector::{VectorSpec, VectorMorph},
+    spectral::{SpectralSpec, SpectralProcessor, FrameKey},
+};
 
 pub enum UnisonFalloff {
     Linear,
@@ -12,48 +22,50 @@ impl UnisonFalloff {
         }
     }
 }
-pub enum UnisonPhase {
-    Random,
-    Zero,
-}
-impl UnisonPhase {
-    fn value(&self) -> f32 {
-        match self {
-            Self::Random => rand::random(),
-            Self::Zero => 0.0,
-        }
-    }
-}
 pub struct UnisonSpec {
     n_voices: u8,
     falloff: UnisonFalloff,
     /// maximum detune in cents
     detune: f32,
-    phase: UnisonPhase,
+    phase: PhaseMode,
 }
 impl UnisonSpec {
     pub fn new(
         n_voices: u8,
         falloff: UnisonFalloff,
         detune: f32,
-        phase: UnisonPhase,
+        phase: PhaseMode,
     ) -> Self {
         Self { n_voices, falloff, detune, phase }
     }
+    pub fn n_voices(&self) -> usize {
+        self.n_voices as usize
+    }
+    /// Gain and frequency multiplier of the `i`th voice.
+    pub fn voice_shape(&self, i: usize) -> (f32, f32) {
+        let n = self.n_voices as usize;
+        // ranges `(0, 1]`, and is `1` at the center.
+        let a = (1 + usize::min(i,n-i-1)) as f32 / ((n+1)/2) as f32;
+        // ranges `[-1,1]`, and is `0` at the center.
+        let b = if n > 1 { (2 * i) as f32 / (n - 1) as f32 - 1.0 } else { 0.0 };
+        (self.falloff.value(a), (self.detune / 1200.0 * b).exp2())
+    }
+    pub fn phase_mode(&self) -> PhaseMode {
+        self.phase
+    }
     fn into_voices(&self) -> Vec<UnisonVoice> {
         let n = self.n_voices as usize;
         let mut voices = Vec::with_capacity(n);
         
         for i in 0 .. n {
-            // ranges `(0, 1]`, and is `1` at the center.
-            let a = (1 + usize::min(i,n-i-1)) as f32 / ((n+1)/2) as f32;
-            // ranges `[-1,1]`, and is `0` at the center.
-            let b = (2 * i) as f32 / (n - 1) as f32 - 1.0;
+            let (gain, freq_off) = self.voice_shape(i);
             voices.push(
                 UnisonVoice {
-                    phase: self.phase.value(),
-                    gain: self.falloff.value(a),
-                    freq_off: (self.detune / 1200.0 * b).exp2(),
+                    phase: 0.0,
+                    phase_reset: 0.0,
+                    direction: 1.0,
+                    gain,
+                    freq_off,
                 }
             );
         }
@@ -63,30 +75,87 @@ impl UnisonSpec {
 }
 struct UnisonVoice {
     phase: f32,
+    /// phase to return to on hard sync
+    phase_reset: f32,
+    /// `-1.0` while running backwards from soft sync
+    direction: f32,
     gain: f32,
     freq_off: f32,
 }
 impl UnisonVoice {
-    fn step(&mut self, sample_rate: f32, base_freq: f32) {
-        increment_phase(&mut self.phase, sample_rate, base_freq * self.freq_off);
+    fn start(&mut self, mode: PhaseMode, base_freq: f32, note_time: f64) {
+        self.phase = mode.start_phase(base_freq * self.freq_off, note_time);
+        self.phase_reset = self.phase;
+    }
+    fn step(&mut self, sample_rate: f32, base_freq: f32) -> bool {
+        increment_phase(&mut self.phase, sample_rate, base_freq * self.freq_off * self.direction)
+    }
+    /// Phase increment per sample, ignoring direction.
+    fn delta(&self, sample_rate: f32, base_freq: f32) -> f32 {
+        base_freq * self.freq_off / sample_rate
+    }
+    /// Hard sync to a wrap `frac` of a sample ago, returns the phase just before the reset.
+    fn hard_sync(&mut self, delta: f32, frac: f32) -> f32 {
+        let phase_at_sync = (self.phase + self.direction * delta * (1.0 - frac)).rem_euclid(1.0);
+        self.direction = 1.0;
+        self.phase = (self.phase_reset + delta * frac).rem_euclid(1.0);
+        phase_at_sync
+    }
+    /// Soft sync to a wrap `frac` of a sample ago.
+    fn soft_sync(&mut self, delta: f32, frac: f32) {
+        let phase_delta = self.direction * delta * (1.0 - 2.0 * frac);
+        self.phase = (self.phase + phase_delta).rem_euclid(1.0);
+        self.direction = -self.direction;
     }
 }
 
 
+/// Which of the shared wavetables an oscillator plays, and how.
+pub enum OscillatorTables {
+    /// The wavetable in `slot`, scanned by `slice`.
+    Single { slot: usize, slice: f32 },
+    /// The four wavetable slots, crossfaded on an X/Y plane.
+    Vector(VectorSpec),
+}
+
+/// The tables an oscillator plays, and the spectral processing applied to them.
+pub struct WavetableSpec {
+    tables: OscillatorTables,
+    spectral: SpectralSpec,
+}
+impl WavetableSpec {
+    pub fn new(
+        tables: OscillatorTables,
+        spectral: SpectralSpec,
+    ) -> Self {
+        Self { tables, spectral }
+    }
+    /// The wavetable in `slot` at `slice`, unprocessed.
+    pub fn single(slot: usize, slice: f32) -> Self {
+        Self::new(OscillatorTables::Single { slot, slice }, SpectralSpec::none())
+    }
+}
+
 pub struct OscillatorSpec {
     unison_spec: UnisonSpec,
     data: CommonDataRef,
-    freq_off: f32,
-    slice: f32,
+    tuning: TuningSpec,
+    wavetable: WavetableSpec,
+    crossmod: CrossModSpec,
+    sync: SyncSpec,
+    warp: PhaseWarpSpec,
 }
 impl OscillatorSpec {
     pub fn new(
         unison_spec: UnisonSpec,
         data: CommonDataRef,
-        freq_off: f32,
-        slice: f32,
+        tuning: TuningSpec,
+        wavetable: WavetableSpec,
+        crossmod: CrossModSpec,
+        sync: SyncSpec,
+        warp: PhaseWarpSpec,
     ) -> Self {
-        Self { unison_spec, data, freq_off, slice }
+        Self { unison_spec, data, tuning, wavetable, crossmod, sync, warp }
     }
 }
 
@@ -97,51 +166,244 @@ pub struct Oscillator {
     spec: OscillatorSpec,
 
     voices: Vec<UnisonVoice>,
+    phase_mode: PhaseMode,
+    /// when the note started on the global clock, in seconds
+    note_time: f64,
+    started: bool,
+    /// wraps of the centre unison voice, for oscillators synced to this one
+    sync_out: Vec<Option<f32>>,
+    /// phase of the centre unison voice, for a sub locked to this one
+    phase_out: Vec<f32>,
+    /// band-limiting correction left over for the next sample after a hard sync
+    blep_carry: f32,
 
+    pub crossmod: CrossModInput,
+    pub sync: SyncInput,
+    warp_mode: PhaseWarpMode,
+    vector_mode: bool,
+
+    pub slot: Param,
     pub slice: Param,
+    pub vector: VectorMorph,
+    pub spectral: SpectralProcessor,
+    pub tuning: Tuning,
     pub freq: Param,
+    pub crossmod_depth: Param,
+    pub warp_amount: Param,
 }
 
 impl Oscillator {
     pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_slot() -> ParamRange { ParamRange::linear(0.0, (WAVETABLE_SLOTS - 1) as f32) }
     pub fn rangeof_slice() -> ParamRange { ParamRange::linear(0.0, 1.0) }
-    pub fn new(sample_rate: f32, spec: OscillatorSpec) -> Self {
+    pub fn rangeof_crossmod_depth() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_warp_amount() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    /// `seed` randomises the spectral phases, so renders of the same voice sound the same.
+    pub fn new(sample_rate: f32, spec: OscillatorSpec, seed: u64) -> Self {
+        let spectral_pool = spec.data.lock().unwrap().spectral_pool.clone();
         Self {
             sample_rate,
 
             buffer: vec![],
             
             voices: spec.unison_spec.into_voices(),
+            phase_mode: spec.unison_spec.phase,
+            note_time: 0.0,
+            started: false,
+            sync_out: vec![],
+            phase_out: vec![],
+            blep_carry: 0.0,
+
+            sync: SyncInput::new(&spec.sync),
+            warp_mode: spec.warp.mode(),
+            warp_amount: Param::new(spec.warp.amount(), Self::rangeof_warp_amount()),
+            crossmod_depth: Param::new(spec.crossmod.depth(), Self::rangeof_crossmod_depth()),
+            crossmod: CrossModInput::new(&spec.crossmod),
             
-            slice: Param::new(spec.slice, Self::rangeof_slice()),
-            freq: Param::new(spec.freq_off, Self::rangeof_freq()),
+            vector_mode: matches!(spec.wavetable.tables, OscillatorTables::Vector(_)),
+            slot: Param::new(Self::single_slot(&spec.wavetable.tables), Self::rangeof_slot()),
+            slice: Param::new(Self::single_slice(&spec.wavetable.tables), Self::rangeof_slice()),
+            vector: match &spec.wavetable.tables {
+                OscillatorTables::Vector(vector) => VectorMorph::new(vector),
+                OscillatorTables::Single { .. } => VectorMorph::new(&VectorSpec::neutral()),
+            },
+            spectral: SpectralProcessor::new(&spec.wavetable.spectral, spectral_pool, seed),
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
 
             spec,
         }
     }
     pub fn update_spec(&mut self, spec: OscillatorSpec) {
-        self.freq.rebase(spec.freq_off);
-        self.slice.rebase(spec.slice);
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.vector_mode = matches!(spec.wavetable.tables, OscillatorTables::Vector(_));
+        self.slot.rebase(Self::single_slot(&spec.wavetable.tables));
+        self.slice.rebase(Self::single_slice(&spec.wavetable.tables));
+        if let OscillatorTables::Vector(vector) = &spec.wavetable.tables {
+            self.vector.update_spec(vector);
+        }
+        self.spectral.update_spec(&spec.wavetable.spectral);
+        self.crossmod_depth.rebase(spec.crossmod.depth());
+        self.crossmod.update_spec(&spec.crossmod);
+        self.sync.update_spec(&spec.sync);
+        self.warp_mode = spec.warp.mode();
+        self.warp_amount.rebase(spec.warp.amount());
+        self.phase_mode = spec.unison_spec.phase;
         // TODO update voices.
     }
+    fn single_slot(tables: &OscillatorTables) -> f32 {
+        match tables {
+            OscillatorTables::Single { slot, .. } => *slot as f32,
+            OscillatorTables::Vector(_) => 0.0,
+        }
+    }
+    fn single_slice(tables: &OscillatorTables) -> f32 {
+        match tables {
+            OscillatorTables::Single { slice, .. } => *slice,
+            OscillatorTables::Vector(_) => 0.5,
+        }
+    }
+    /// Slot a `slot` param value selects.
+    fn slot_index(slot: f32) -> usize {
+        (slot.round().max(0.0) as usize).min(WAVETABLE_SLOTS - 1)
+    }
+    /// Set when the note started on the global clock, for free-running phase.
+    pub fn set_note_time(&mut self, note_time: f64) {
+        self.note_time = note_time;
+    }
+    /// Whether rendering this needs `oscs[i]` of the same voice to be rendered first.
+    pub fn depends_on_osc(&self, i: usize) -> bool {
+        (self.crossmod.is_active() && matches!(self.crossmod.source, CrossModSource::Osc(j) if j == i)) ||
+            (self.sync.is_active() && matches!(self.sync.source, SyncSource::Osc(j) if j == i))
+    }
     pub fn block(&mut self, trigger_at: usize, block_len: usize) {
         self.buffer.clear();
+        self.sync_out.clear();
+        self.phase_out.clear();
         
-        let wavetable = &self.spec.data.lock().unwrap().wavetable;
+        // Hold the tables rather than the lock, so loading a table never waits on a block.
+        let tables = self.spec.data.lock().unwrap().wavetables.clone();
+        let vector_mode = self.vector_mode;
+        let slot = self.slot.take(block_len);
         let slice = self.slice.take(block_len);
+        let vector_x = self.vector.x.take(block_len);
+        let vector_y = self.vector.y.take(block_len);
+        let [s0, s1, s2, s3] = &mut self.vector.slices;
+        let vector_slices = [s0.take(block_len), s1.take(block_len), s2.take(block_len), s3.take(block_len)];
         let freq = self.freq.take(block_len);
+        let crossmod_depth = self.crossmod_depth.take(block_len);
+        let warp_mode = self.warp_mode;
+        let warp_amount = self.warp_amount.take(block_len);
+        let crossmod_mode = self.crossmod.mode;
+        let crossmod = self.crossmod.take(block_len);
+        let sync_mode = self.sync.mode;
+        let sync = self.sync.take(block_len);
+        let centre = self.voices.len() / 2;
+
+        let spectral = block_len > 0 && self.spectral.prepare();
+        if spectral {
+            // The frame is processed once per block, from where the tables are at its start.
+            let weights = if vector_mode {
+                VectorMorph::weights(vector_x[0], vector_y[0])
+            } else {
+                std::array::from_fn(|k| if k == Self::slot_index(slot[0]) { 1.0 } else { 0.0 })
+            };
+            let slices = if vector_mode {
+                vector_slices.map(|slices| slices[0])
+            } else {
+                [slice[0]; 4]
+            };
+            let key = FrameKey::new(std::array::from_fn(|k| tables[k].clone()), slices, weights);
+            let fill = |frame: &mut [f32]| {
+                frame.fill(0.0);
+                for (k, table) in tables.iter().enumerate().take(4) {
+                    if weights[k] > 0.0 {
+                        for (out, value) in frame.iter_mut().zip(table.data.frame(slices[k])) {
+                            *out += value * weights[k];
+                        }
+                    }
+                }
+            };
+            let max_harmonic = self.sample_rate * 0.5 / freq[0].max(1.0);
+            self.spectral.block(block_len, key, fill, max_harmonic);
+        }
+        let processed = &self.spectral;
+
         for i in 0 .. block_len {
-            let mut value = 0.0;
+            if i == trigger_at && !self.started {
+                self.started = true;
+                for voice in &mut self.voices {
+                    voice.start(self.phase_mode, freq[i], self.note_time);
+                }
+            }
+            let (m, depth) = match crossmod {
+                Some(crossmod) => (crossmod[i], crossmod_depth[i]),
+                None => (0.0, 0.0),
+            };
+            let weights = VectorMorph::weights(vector_x[i], vector_y[i]);
+            let read = |phase: f32| {
+                if spectral {
+                    return processed.sample(phase);
+                }
+                if !vector_mode {
+                    return tables[Self::slot_index(slot[i])].data.sample(phase, slice[i]);
+                }
+                let mut value = 0.0;
+                for (k, table) in tables.iter().enumerate().take(4) {
+                    if weights[k] > 0.0 {
+                        value += table.data.sample(phase, vector_slices[k][i]) * weights[k];
+                    }
+                }
+                value
+            };
+            let sample = |phase: f32| {
+                let phase = crossmod_mode.apply_phase(phase, m, depth);
+                let warped = warp_mode.warp(phase, warp_amount[i]);
+                read(warped) * warp_mode.gain(phase, warp_amount[i])
+            };
+
+            self.phase_out.push(self.voices.get(centre).map_or(0.0, |voice| voice.phase));
+            let mut value = std::mem::replace(&mut self.blep_carry, 0.0);
             for voice in &self.voices {
-                value += wavetable.data.sample(voice.phase, slice[i]) * voice.gain;
+                value += sample(voice.phase) * voice.gain;
             }
-            self.buffer.push(value);
 
+            let mut sync_event = None;
             if i > trigger_at {
-                for voice in &mut self.voices {
-                    voice.step(self.sample_rate, freq[i]);
+                let freq = crossmod_mode.apply_freq(freq[i], m, depth);
+                let sync_at = sync.and_then(|sync| sync[i]);
+                // Size of the jump in the output caused by hard sync.
+                let mut step = 0.0;
+                for (j, voice) in self.voices.iter_mut().enumerate() {
+                    let delta = voice.delta(self.sample_rate, freq);
+                    match (sync_mode, sync_at) {
+                        (SyncMode::Hard, Some(frac)) => {
+                            let phase_at_sync = voice.hard_sync(delta, frac);
+                            step += (sample(voice.phase_reset) - sample(phase_at_sync)) * voice.gain;
+                            if j == centre {
+                                sync_event = Some(frac);
+                            }
+                        }
+                        (SyncMode::Soft, Some(frac)) => {
+                            // The output stays continuous, so there is nothing to band-limit.
+                            voice.soft_sync(delta, frac);
+                        }
+                        _ => {
+                            if voice.step(self.sample_rate, freq) && j == centre && delta > 0.0 {
+                                sync_event = Some((voice.phase / delta).min(1.0));
+                            }
+                        }
+                    }
+                }
+                if let Some(frac) = sync_at {
+                    value += step * polyblep_before(frac);
+                    self.blep_carry = step * polyblep_after(frac);
                 }
             }
+
+            self.buffer.push(crossmod_mode.apply_amplitude(value, m, depth));
+            self.sync_out.push(sync_event);
         }
     }
 }
@@ -150,4 +412,14 @@ impl ParamSource for Oscillator {
     fn source_param_buffer(&self) -> &Vec<f32> {
         &self.buffer
     }
+}
+impl SyncMaster for Oscillator {
+    fn sync_events(&self) -> &Vec<Option<f32>> {
+        &self.sync_out
+    }
+}
+impl PhaseMaster for Oscillator {
+    fn phases(&self) -> &Vec<f32> {
+        &self.phase_out
+    }
 }
\ No newline at end of file
@@ -2,8 +2,11 @@ use nih_plug::prelude::{Smoother, SmoothingStyle};
 
 use crate::util::param_range::ParamRange;
 
+use super::tuning::Tuning;
+
 pub struct InputFrequencyParam {
-    midi_note: u8,
+    /// the MIDI note played, which moves when a mono voice takes over a new note
+    pub note: InputParam,
     pub pitchbend: InputParam,
     // modulation_oct: f32,
     buffer: Vec<f32>,
@@ -14,15 +17,16 @@ impl InputFrequencyParam {
         sample_rate: f32,
         midi_note: u8,
         start_pitchbend: f32,
+        oversampling: usize,
     ) -> Self {
         Self {
-            midi_note,
-            pitchbend: InputParam::new(sample_rate, start_pitchbend, SmoothingStyle::None),
+            note: InputParam::new(sample_rate, midi_note as f32, SmoothingStyle::None, oversampling),
+            pitchbend: InputParam::new(sample_rate, start_pitchbend, SmoothingStyle::None, oversampling),
             buffer: vec![],
         }
     }
     pub fn prepare(&mut self) {
-        self.buffer = self.pitchbend.buffer.iter().map(|v| nih_plug::util::f32_midi_note_to_freq(v + self.midi_note as f32)).collect()
+        self.buffer = self.pitchbend.buffer.iter().zip(&self.note.buffer).map(|(v, note)| nih_plug::util::f32_midi_note_to_freq(v + note)).collect()
     }
     pub fn get(&self) -> &Vec<f32> {
         &self.buffer
@@ -31,6 +35,8 @@ impl InputFrequencyParam {
 
 pub struct InputParam {
     sample_rate: f32,
+    /// samples rendered per host sample, which `update_block` and `finalize_block` count in
+    oversampling: usize,
     current: Smoother<f32>,
     buffer: Vec<f32>,
 }
@@ -40,11 +46,13 @@ impl InputParam {
         sample_rate: f32,
         start_value: f32,
         smoothing_style: SmoothingStyle,
+        oversampling: usize,
     ) -> Self {
         let current = Smoother::new(smoothing_style);
         current.reset(start_value);
         Self {
             sample_rate,
+            oversampling,
             current,
             buffer: vec![],
         }
@@ -58,11 +66,11 @@ impl InputParam {
         self.buffer.clear();
     }
     pub fn update_block(&mut self, sample_id: usize, new_value: f32) {
-        self.extend_buffer_to_len(sample_id);
+        self.extend_buffer_to_len(sample_id * self.oversampling);
         self.current.set_target(self.sample_rate, new_value);
     }
     pub fn finalize_block(&mut self, len: usize) {
-        self.extend_buffer_to_len(len);
+        self.extend_buffer_to_len(len * self.oversampling);
     }
 }
 impl ParamSource for InputParam {
@@ -194,6 +202,9 @@ impl Param {
     pub fn rebase(&mut self, value_base: f32) {
         self.value_base = value_base;
     }
+    pub fn base(&self) -> f32 {
+        self.value_base
+    }
 
     pub fn send<T : ParamSource>(&mut self, param: &T, polarity: ParamPolarity, mag: f32) {
         let param_data = param.get_param_buffer(polarity);
@@ -210,7 +221,15 @@ impl Param {
     pub fn send_key_track(&mut self, freq: &InputFrequencyParam) {
         self.send_key_track_withmag(freq, 1.0);
     }
+    /// Keytrack through a component's coarse and fine tuning.
+    pub fn send_key_track_tuned(&mut self, freq: &InputFrequencyParam, tuning: &Tuning) {
+        self.send_key_track_mapped(freq, |freq| tuning.apply(freq));
+    }
     pub fn send_key_track_withmag(&mut self, freq: &InputFrequencyParam, mag: f32) {
+        self.send_key_track_mapped(freq, |freq| freq * mag);
+    }
+    /// Keytrack `map` of the frequency.
+    fn send_key_track_mapped(&mut self, freq: &InputFrequencyParam, map: impl Fn(f32) -> f32) {
         let freq_data = freq.get();
         let block_len = freq_data.len();
 
@@ -220,7 +239,7 @@ impl Param {
         }
 
         for i in 0 .. block_len {
-            self.value_keytrack_buffer[i] = freq_data[i] * mag;
+            self.value_keytrack_buffer[i] = map(freq_data[i]);
         }
     }
 
@@ -0,0 +1,25 @@
+/// How a component picks its phase when a note starts.
+#[derive(Clone, Copy)]
+pub enum PhaseMode {
+    /// Start every note at this phase.
+    Retrigger(f32),
+    /// Start at `phase`, pushed forward at random by up to `amount` of a cycle.
+    Random { phase: f32, amount: f32 },
+    /// Pick up where a global oscillator at the same frequency would be, so notes
+    /// carry on one continuous cycle instead of restarting it.
+    FreeRunning,
+}
+impl PhaseMode {
+    /// Phase to start from, for a note triggered `note_time` seconds into the global clock.
+    pub fn start_phase(&self, freq: f32, note_time: f64) -> f32 {
+        match self {
+            Self::Retrigger(phase) => phase.rem_euclid(1.0),
+            Self::Random { phase, amount } => (phase + amount * rand::random::<f32>()).rem_euclid(1.0),
+            Self::FreeRunning => (freq as f64 * note_time).rem_euclid(1.0) as f32,
+        }
+    }
+    /// Whether a new note restarts the phase, instead of it carrying on.
+    pub fn retriggers(&self) -> bool {
+        !matches!(self, Self::FreeRunning)
+    }
+}
\ No newline at end of file
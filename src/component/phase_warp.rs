@@ -0,0 +1,88 @@
+use crate::util::lerpable::Lerpable;
+
+/// How the shortest pulse the squeeze and sync window warps can reach compares to a full cycle.
+const MIN_WIDTH: f32 = 0.02;
+/// How many extra cycles the sync window fits into one at full amount.
+const SYNC_RANGE: f32 = 15.0;
+
+#[derive(Clone, Copy)]
+pub enum PhaseWarpMode {
+    None,
+    /// Spend less time around the middle of the cycle.
+    BendPlus,
+    /// Spend more time around the middle of the cycle.
+    BendMinus,
+    /// Play the whole cycle in a shorter pulse and hold the start for the rest.
+    Squeeze,
+    /// Play the cycle forwards and then back.
+    Mirror,
+    /// Snap the phase to fewer and fewer steps.
+    Quantize,
+    /// Play several cycles per cycle, faded out at the edges.
+    SyncWindow,
+    /// Casio CZ style phase distortion, the first half of the cycle gets shorter.
+    PhaseDistortion,
+}
+impl PhaseWarpMode {
+    pub fn warp(&self, phase: f32, amount: f32) -> f32 {
+        match self {
+            Self::None => phase,
+            Self::BendPlus | Self::BendMinus => {
+                let exp = 1.0 + 4.0 * amount;
+                let exp = if let Self::BendPlus = self { exp.recip() } else { exp };
+                let centered = phase * 2.0 - 1.0;
+                (centered.signum() * centered.abs().powf(exp) + 1.0) * 0.5
+            }
+            Self::Squeeze => {
+                let width = amount.lerp(1.0, MIN_WIDTH);
+                if phase < width { phase / width } else { 0.0 }
+            }
+            Self::Mirror => amount.lerp(phase, 1.0 - (phase * 2.0 - 1.0).abs()),
+            Self::Quantize => {
+                if amount <= 0.0 {
+                    return phase;
+                }
+                let steps = ((1.0 - amount) * 8.0).exp2().max(2.0);
+                (phase * steps).floor() / steps
+            }
+            Self::SyncWindow => (phase * (1.0 + amount * SYNC_RANGE)).fract(),
+            Self::PhaseDistortion => {
+                let knee = amount.lerp(0.5, MIN_WIDTH);
+                if phase < knee {
+                    0.5 * phase / knee
+                } else {
+                    0.5 + 0.5 * (phase - knee) / (1.0 - knee)
+                }
+            }
+        }
+    }
+    /// Gain applied on top of the warped waveform, used to hide the jumps of the sync window.
+    pub fn gain(&self, phase: f32, amount: f32) -> f32 {
+        match self {
+            Self::SyncWindow => amount.lerp(1.0, (phase * std::f32::consts::PI).sin()),
+            _ => 1.0,
+        }
+    }
+}
+
+pub struct PhaseWarpSpec {
+    mode: PhaseWarpMode,
+    amount: f32,
+}
+impl PhaseWarpSpec {
+    pub fn new(
+        mode: PhaseWarpMode,
+        amount: f32,
+    ) -> Self {
+        Self { mode, amount }
+    }
+    pub fn none() -> Self {
+        Self::new(PhaseWarpMode::None, 0.0)
+    }
+    pub fn mode(&self) -> PhaseWarpMode {
+        self.mode
+    }
+    pub fn amount(&self) -> f32 {
+        self.amount
+    }
+}
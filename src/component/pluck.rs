@@ -0,0 +1,244 @@
+use crate::{util::{seeded_rng::SeededRng, param_range::ParamRange, lerpable::Lerpable}, note::state::NoteState};
+
+use super::{params::{ParamSource, ParamSourceImpl, ParamPolarity, Param}, crossmod::CrossModSource, tuning::{TuningSpec, Tuning}};
+
+/// What sets the string moving when the note starts.
+pub struct PluckExcitationSpec {
+    source: Option<CrossModSource>,
+    /// `0.0` is only the noise burst, `1.0` only the other component
+    mix: f32,
+}
+impl PluckExcitationSpec {
+    /// A burst of white noise one period long.
+    pub fn noise() -> Self {
+        Self { source: None, mix: 0.0 }
+    }
+    pub fn from_source(
+        source: CrossModSource,
+        mix: f32,
+    ) -> Self {
+        Self { source: Some(source), mix }
+    }
+}
+
+pub struct PluckedStringSpec {
+    tuning: TuningSpec,
+    /// time to fall by 60dB, in seconds
+    decay: f32,
+    damping: f32,
+    /// where along the string it is plucked, `0.5` being the middle
+    pick_position: f32,
+    stiffness: f32,
+    excitation: PluckExcitationSpec,
+}
+impl PluckedStringSpec {
+    pub fn new(
+        tuning: TuningSpec,
+        decay: f32,
+        damping: f32,
+        pick_position: f32,
+        stiffness: f32,
+        excitation: PluckExcitationSpec,
+    ) -> Self {
+        Self { tuning, decay, damping, pick_position, stiffness, excitation }
+    }
+}
+
+/// Karplus-Strong string: a delay line one period long, fed back through a damping
+/// lowpass and a dispersion allpass.
+pub struct PluckedString {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    line: Vec<f32>,
+    /// excitation history, for the pick position comb filter
+    pick_line: Vec<f32>,
+    write_i: usize,
+    rng: SeededRng,
+    /// samples of noise burst left to play
+    burst_left: usize,
+    triggered: bool,
+    /// where the next block plucks the string again, as the voice is played again
+    retrigger_at: Option<usize>,
+    damping_last: f32,
+    allpass_x: f32,
+    allpass_y: f32,
+    /// smoothed output power, to tell when the string has died out
+    energy: f32,
+
+    pub excitation_source: Option<CrossModSource>,
+    excitation: Vec<f32>,
+    excitation_sent: bool,
+
+    pub tuning: Tuning,
+    pub freq: Param,
+    pub decay: Param,
+    pub damping: Param,
+    pub pick_position: Param,
+    pub stiffness: Param,
+    pub excitation_mix: Param,
+}
+
+impl PluckedString {
+    /// lowest frequency the delay line is long enough for
+    const MIN_FREQ: f32 = 20.0;
+    /// output power below which the string counts as silent, about -90dB
+    const SILENCE: f32 = 1e-9;
+    /// time constant of the energy follower, in seconds
+    const ENERGY_TIME: f32 = 0.05;
+
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_decay() -> ParamRange { ParamRange::exponential(0.05, 30.0) }
+    pub fn rangeof_damping() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_pick_position() -> ParamRange { ParamRange::linear(0.0, 0.5) }
+    pub fn rangeof_stiffness() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_excitation_mix() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    /// `seed` draws the noise burst, so renders of the same voice pluck the same.
+    pub fn new(sample_rate: f32, spec: PluckedStringSpec, seed: u64) -> Self {
+        let len = (sample_rate / Self::MIN_FREQ) as usize + 4;
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            line: vec![0.0; len],
+            pick_line: vec![0.0; len],
+            write_i: 0,
+            rng: SeededRng::new(seed),
+            burst_left: 0,
+            triggered: false,
+            retrigger_at: None,
+            damping_last: 0.0,
+            allpass_x: 0.0,
+            allpass_y: 0.0,
+            energy: 0.0,
+
+            excitation_source: spec.excitation.source,
+            excitation: vec![],
+            excitation_sent: false,
+
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+            decay: Param::new(spec.decay, Self::rangeof_decay()),
+            damping: Param::new(spec.damping, Self::rangeof_damping()),
+            pick_position: Param::new(spec.pick_position, Self::rangeof_pick_position()),
+            stiffness: Param::new(spec.stiffness, Self::rangeof_stiffness()),
+            excitation_mix: Param::new(spec.excitation.mix, Self::rangeof_excitation_mix()),
+        }
+    }
+    pub fn update_spec(&mut self, spec: PluckedStringSpec) {
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.decay.rebase(spec.decay);
+        self.damping.rebase(spec.damping);
+        self.pick_position.rebase(spec.pick_position);
+        self.stiffness.rebase(spec.stiffness);
+        self.excitation_source = spec.excitation.source;
+        self.excitation_mix.rebase(spec.excitation.mix);
+    }
+    /// Pluck the string again `at` samples into the next block, over whatever still rings.
+    pub fn retrigger(&mut self, at: usize) {
+        self.retrigger_at = Some(at);
+    }
+    /// Excite the string with another component's output this block.
+    pub fn send_excitation<T : ParamSource>(&mut self, source: &T) {
+        self.excitation = source.get_param_buffer(ParamPolarity::Bipolar);
+        self.excitation_sent = true;
+    }
+    /// Linearly interpolated read from `delay` samples ago.
+    fn read(line: &[f32], write_i: usize, delay: f32) -> f32 {
+        let len = line.len();
+        let delay = delay.clamp(1.0, (len - 2) as f32);
+        let whole = delay as usize;
+        let a = line[(write_i + len - whole) % len];
+        let b = line[(write_i + len - whole - 1) % len];
+        (delay - whole as f32).lerp(a, b)
+    }
+    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+        self.buffer.clear();
+
+        let freq = self.freq.take(block_len);
+        let decay = self.decay.take(block_len);
+        let damping = self.damping.take(block_len);
+        let pick_position = self.pick_position.take(block_len);
+        let stiffness = self.stiffness.take(block_len);
+        let excitation_mix = self.excitation_mix.take(block_len);
+        let excitation_sent = std::mem::replace(&mut self.excitation_sent, false);
+        let excitation = if excitation_sent && self.excitation.len() == block_len {
+            Some(&self.excitation)
+        } else {
+            None
+        };
+
+        let retrigger_at = self.retrigger_at.take();
+        let energy_k = (-1.0 / (Self::ENERGY_TIME * self.sample_rate)).exp();
+        for i in 0 .. block_len {
+            if i < trigger_at && !self.triggered {
+                self.buffer.push(0.0);
+                continue;
+            }
+            let period = self.sample_rate / freq[i].max(Self::MIN_FREQ);
+            if !self.triggered || Some(i) == retrigger_at {
+                self.triggered = true;
+                self.burst_left = period as usize;
+            }
+
+            let mut noise = 0.0;
+            if self.burst_left > 0 {
+                self.burst_left -= 1;
+                noise = self.rng.next_bipolar();
+            }
+            let input = match excitation {
+                Some(excitation) => excitation_mix[i].lerp(noise, excitation[i]),
+                None => noise * (1.0 - excitation_mix[i]),
+            };
+            // Plucking at a fraction of the length cancels the harmonics with a node there.
+            let pick_delay = pick_position[i] * period;
+            let mut excite = input;
+            if pick_delay >= 1.0 {
+                excite -= Self::read(&self.pick_line, self.write_i, pick_delay);
+            }
+            self.pick_line[self.write_i] = input;
+
+            // Both filters delay the loop, so the line is shortened to keep it in tune.
+            let s = damping[i] * 0.5;
+            let a = -stiffness[i] * 0.7;
+            let delay = period - s - (1.0 - a) / (1.0 + a);
+
+            let delayed = Self::read(&self.line, self.write_i, delay);
+            let damped = s.lerp(delayed, self.damping_last);
+            self.damping_last = delayed;
+            let dispersed = a * damped + self.allpass_x - a * self.allpass_y;
+            self.allpass_x = damped;
+            self.allpass_y = dispersed;
+
+            let gain = 0.001f32.powf(period / self.sample_rate / decay[i]);
+            let value = excite + dispersed * gain;
+            self.line[self.write_i] = value;
+            self.write_i = (self.write_i + 1) % self.line.len();
+
+            self.energy = value * value + (self.energy - value * value) * energy_k;
+            self.buffer.push(value);
+        }
+    }
+    /// Output silence for the block instead of rendering it, for when nothing would hear it.
+    pub fn skip(&mut self, block_len: usize) {
+        self.retrigger_at = None;
+        self.buffer.clear();
+        self.buffer.resize(block_len, 0.0);
+    }
+    /// Whether the string was plucked and has since died out.
+    pub fn is_silent(&self) -> bool {
+        self.triggered && self.burst_left == 0 && self.energy < Self::SILENCE
+    }
+    pub fn update_note_ended(&self, state: &mut NoteState) {
+        if self.is_silent() {
+            state.mark_ended();
+        }
+    }
+}
+impl ParamSource for PluckedString {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
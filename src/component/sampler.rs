@@ -0,0 +1,311 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{util::{param_range::ParamRange, lerpable::Lerpable}, common_data::CommonDataRef, note::state::NoteState};
+
+use super::{params::{ParamSource, ParamPolarity, Param}, wavetable::Wav, sfz::{SfzRegion, SfzLoopMode}, tuning::{TuningSpec, Tuning}};
+
+/// Mono audio loaded for playback, with the rate it was recorded at.
+pub struct Sample {
+    pub data: Vec<f32>,
+    pub sample_rate: f32,
+}
+impl Sample {
+    pub fn from_filepath(file_path: &Path) -> Option<Self> {
+        let (header, data) = Wav::from_filepath_with_header(file_path)?;
+        Some(Self::from_wav(&header, data))
+    }
+    /// Mix the interleaved channels of a decoded .wav down to mono.
+    pub fn from_wav(header: &wav::Header, data: Vec<f32>) -> Self {
+        let channels = (header.channel_count as usize).max(1);
+        let data = if channels == 1 {
+            data
+        } else {
+            data.chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect()
+        };
+        Self {
+            data,
+            sample_rate: header.sampling_rate as f32,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    /// Linearly interpolated read, silent outside the sample.
+    pub fn read(&self, position: f64) -> f32 {
+        if position < 0.0 {
+            return 0.0;
+        }
+        let i = position as usize;
+        if i + 1 >= self.data.len() {
+            return if i < self.data.len() { self.data[i] } else { 0.0 };
+        }
+        let k = (position - i as f64) as f32;
+        k.lerp(self.data[i], self.data[i + 1])
+    }
+}
+impl Default for Sample {
+    fn default() -> Self {
+        Self {
+            data: vec![],
+            sample_rate: 44100.0,
+        }
+    }
+}
+
+pub enum SamplerMode {
+    OneShot,
+    /// Loop between the loop start and end once the playhead reaches them.
+    Loop,
+    /// Like `Loop` while the note is held, then play on to the end of the sample.
+    LoopSustain,
+}
+
+pub struct SamplerLoopSpec {
+    mode: SamplerMode,
+    start: f32,
+    end: f32,
+    /// crossfade length as a fraction of the loop length
+    crossfade: f32,
+}
+impl SamplerLoopSpec {
+    pub fn one_shot() -> Self {
+        Self { mode: SamplerMode::OneShot, start: 0.0, end: 1.0, crossfade: 0.0 }
+    }
+    pub fn looped(
+        start: f32,
+        end: f32,
+        crossfade: f32,
+    ) -> Self {
+        Self { mode: SamplerMode::Loop, start, end, crossfade }
+    }
+}
+
+pub struct SamplerSpec {
+    data: CommonDataRef,
+    /// the midi note the sample plays at its original pitch
+    root_key: u8,
+    reverse: bool,
+    tuning: TuningSpec,
+    start: f32,
+    loop_spec: SamplerLoopSpec,
+}
+impl SamplerSpec {
+    pub fn new(
+        data: CommonDataRef,
+        root_key: u8,
+        reverse: bool,
+        tuning: TuningSpec,
+        start: f32,
+        loop_spec: SamplerLoopSpec,
+    ) -> Self {
+        Self { data, root_key, reverse, tuning, start, loop_spec }
+    }
+}
+
+enum SamplerState {
+    Waiting,
+    Playing,
+    Done,
+}
+
+pub struct Sampler {
+    sample_rate: f32,
+
+    buffer: Vec<f32>,
+    buffer_l: Vec<f32>,
+    buffer_r: Vec<f32>,
+    sample: Arc<Sample>,
+    root_freq: f32,
+    mode: SamplerMode,
+    reverse: bool,
+    /// play the whole sample whatever the note does, as SFZ `one_shot` regions do
+    ignore_release: bool,
+    held: bool,
+    gain: f32,
+    /// `-1.0` (left) to `1.0` (right)
+    pan: f32,
+
+    state: SamplerState,
+    position: f64,
+    /// where the next block starts the sample over, as the voice is played again
+    retrigger_at: Option<usize>,
+
+    pub tuning: Tuning,
+    pub freq: Param,
+    pub start: Param,
+    pub loop_start: Param,
+    pub loop_end: Param,
+    pub crossfade: Param,
+}
+
+impl Sampler {
+    pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_position() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_crossfade() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn new(sample_rate: f32, spec: SamplerSpec) -> Self {
+        Self {
+            sample_rate,
+
+            buffer: vec![],
+            buffer_l: vec![],
+            buffer_r: vec![],
+            sample: spec.data.lock().unwrap().sample.clone(),
+            root_freq: nih_plug::util::midi_note_to_freq(spec.root_key),
+            mode: spec.loop_spec.mode,
+            reverse: spec.reverse,
+            ignore_release: false,
+            held: true,
+            gain: 1.0,
+            pan: 0.0,
+
+            state: SamplerState::Waiting,
+            position: 0.0,
+            retrigger_at: None,
+
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+            start: Param::new(spec.start, Self::rangeof_position()),
+            loop_start: Param::new(spec.loop_spec.start, Self::rangeof_position()),
+            loop_end: Param::new(spec.loop_spec.end, Self::rangeof_position()),
+            crossfade: Param::new(spec.loop_spec.crossfade, Self::rangeof_crossfade()),
+        }
+    }
+    pub fn update_spec(&mut self, spec: SamplerSpec) {
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.start.rebase(spec.start);
+        self.loop_start.rebase(spec.loop_spec.start);
+        self.loop_end.rebase(spec.loop_spec.end);
+        self.crossfade.rebase(spec.loop_spec.crossfade);
+        self.root_freq = nih_plug::util::midi_note_to_freq(spec.root_key);
+        self.mode = spec.loop_spec.mode;
+        self.reverse = spec.reverse;
+    }
+    /// Play a region of an SFZ instrument instead of the shared sample.
+    pub fn load_sfz_region(&mut self, region: &SfzRegion) {
+        let len = region.sample.len().max(1) as f32;
+        self.sample = region.sample.clone();
+        self.root_freq = nih_plug::util::midi_note_to_freq(region.pitch_keycenter)
+            * (-region.tune / 1200.0).exp2();
+        self.start.rebase(region.offset as f32 / len);
+        self.mode = match region.loop_mode {
+            SfzLoopMode::NoLoop | SfzLoopMode::OneShot => SamplerMode::OneShot,
+            SfzLoopMode::LoopContinuous => SamplerMode::Loop,
+            SfzLoopMode::LoopSustain => SamplerMode::LoopSustain,
+        };
+        self.ignore_release = matches!(region.loop_mode, SfzLoopMode::OneShot);
+        self.loop_start.rebase(region.loop_start as f32 / len);
+        self.loop_end.rebase((region.loop_end + 1) as f32 / len);
+        self.crossfade.rebase(0.0);
+        self.gain = nih_plug::util::db_to_gain(region.volume);
+        self.pan = region.pan / 100.0;
+    }
+    /// Whether note-off should be ignored, letting the sample play out.
+    pub fn ignores_release(&self) -> bool {
+        self.ignore_release
+    }
+    /// Play the sample from its start again `at` samples into the next block.
+    pub fn retrigger(&mut self, at: usize) {
+        self.retrigger_at = Some(at);
+    }
+    /// Stop a `LoopSustain` loop, so the sample plays on past it.
+    pub fn release(&mut self) {
+        self.held = false;
+    }
+    /// End the note once the sample has played out, for samples that ignore note-off.
+    pub fn update_note_ended(&self, state: &mut NoteState) {
+        if matches!(self.state, SamplerState::Done) {
+            state.mark_ended();
+        }
+    }
+    pub fn block(&mut self, trigger_at: usize, block_len: usize) {
+        self.buffer.clear();
+        self.buffer_l.clear();
+        self.buffer_r.clear();
+
+        let freq = self.freq.take(block_len);
+        let start = self.start.take(block_len);
+        let loop_start = self.loop_start.take(block_len);
+        let loop_end = self.loop_end.take(block_len);
+        let crossfade = self.crossfade.take(block_len);
+
+        let sample = &self.sample;
+        let len = sample.len() as f64;
+        let rate_k = sample.sample_rate / self.sample_rate / self.root_freq;
+        let retrigger_at = self.retrigger_at.take();
+        for i in 0 .. block_len {
+            if Some(i) == retrigger_at {
+                self.state = SamplerState::Waiting;
+                self.held = true;
+            }
+            if i == trigger_at || Some(i) == retrigger_at {
+                if let SamplerState::Waiting = self.state {
+                    let start = start[i] as f64 * len;
+                    self.position = if self.reverse { len - 1.0 - start } else { start };
+                    self.state = if len > 1.0 { SamplerState::Playing } else { SamplerState::Done };
+                }
+            }
+            if !matches!(self.state, SamplerState::Playing) {
+                self.buffer.push(0.0);
+                self.buffer_l.push(0.0);
+                self.buffer_r.push(0.0);
+                continue;
+            }
+
+            let rate = (freq[i] * rate_k) as f64;
+            let loop_start = loop_start[i] as f64 * len;
+            let loop_end = loop_end[i] as f64 * len;
+            let loop_len = loop_end - loop_start;
+            let looping = match self.mode {
+                SamplerMode::OneShot => false,
+                SamplerMode::Loop => true,
+                SamplerMode::LoopSustain => self.held,
+            } && loop_len >= 1.0;
+            let pos = self.position;
+
+            let mut value = sample.read(pos);
+            if !self.reverse {
+                // Fade towards the audio just before the loop start, so the jump back is seamless.
+                let xfade = (crossfade[i] as f64 * loop_len).min(loop_start);
+                if looping && xfade > 0.0 && pos < loop_end && pos >= loop_end - xfade {
+                    let k = ((pos - (loop_end - xfade)) / xfade) as f32;
+                    value = k.lerp(value, sample.read(pos - loop_len));
+                }
+                self.position += rate;
+                if looping && pos < loop_end && self.position >= loop_end {
+                    self.position -= loop_len;
+                } else if self.position >= len - 1.0 {
+                    self.state = SamplerState::Done;
+                }
+            } else {
+                let xfade = (crossfade[i] as f64 * loop_len).min(len - loop_end);
+                if looping && xfade > 0.0 && pos >= loop_start && pos < loop_start + xfade {
+                    let k = ((loop_start + xfade - pos) / xfade) as f32;
+                    value = k.lerp(value, sample.read(pos + loop_len));
+                }
+                self.position -= rate;
+                if looping && pos >= loop_start && self.position < loop_start {
+                    self.position += loop_len;
+                } else if self.position < 0.0 {
+                    self.state = SamplerState::Done;
+                }
+            }
+            let value = value * self.gain;
+            self.buffer.push(value);
+            self.buffer_l.push(value * (1.0 - self.pan).min(1.0));
+            self.buffer_r.push(value * (1.0 + self.pan).min(1.0));
+        }
+    }
+    /// Left and right output, after panning.
+    pub fn stereo_buffers(&self) -> [&Vec<f32>; 2] {
+        [&self.buffer_l, &self.buffer_r]
+    }
+}
+impl ParamSource for Sampler {
+    const POLARITY: ParamPolarity = ParamPolarity::Bipolar;
+    fn source_param_buffer(&self) -> &Vec<f32> {
+        &self.buffer
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{note::id::NoteId, util::fnv::Fnv1a64};
+
+use super::{sampler::Sample, wavetable::Wav};
+
+/// The opcodes of one `<region>`, with everything inherited from its headers merged in.
+pub struct SfzRegionSpec {
+    /// index of the `<group>` the region belongs to, for round-robin
+    pub group: usize,
+    pub opcodes: HashMap<String, String>,
+}
+
+pub struct SfzFile {
+    pub default_path: String,
+    pub regions: Vec<SfzRegionSpec>,
+}
+impl SfzFile {
+    pub fn parse(text: &str) -> Self {
+        let mut parser = SfzParser::default();
+        for line in text.lines() {
+            let line = match line.find("//") {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            parser.parse_line(line);
+        }
+        parser.finish()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SfzHeader {
+    None,
+    Control,
+    Global,
+    Master,
+    Group,
+    Region,
+}
+
+#[derive(Default)]
+struct SfzParser {
+    header: Option<SfzHeader>,
+    control: HashMap<String, String>,
+    global: HashMap<String, String>,
+    master: HashMap<String, String>,
+    group: HashMap<String, String>,
+    region: HashMap<String, String>,
+    group_i: usize,
+    regions: Vec<SfzRegionSpec>,
+}
+impl SfzParser {
+    fn parse_line(&mut self, line: &str) {
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(header) = rest.strip_prefix('<') {
+                let end = header.find('>').unwrap_or(header.len());
+                self.begin_header(&header[..end]);
+                rest = header.get(end + 1 ..).unwrap_or("");
+                continue;
+            }
+            let eq = match rest.find('=') {
+                Some(eq) => eq,
+                None => break,
+            };
+            let key = rest[..eq].trim();
+            let after = &rest[eq + 1 ..];
+            let end = Self::value_end(after);
+            self.opcode(key, after[..end].trim());
+            rest = &after[end ..];
+        }
+    }
+    /// Values (sample paths especially) may contain spaces, so they run up to the next header
+    /// or the whitespace before the next `key=`.
+    fn value_end(s: &str) -> usize {
+        let mut end = s.find('<').unwrap_or(s.len());
+        if let Some(eq) = s[..end].find('=') {
+            if let Some(ws) = s[..eq].trim_end().rfind(char::is_whitespace) {
+                end = ws;
+            }
+        }
+        end
+    }
+    fn begin_header(&mut self, name: &str) {
+        self.end_region();
+        let header = match name.trim() {
+            "control" => SfzHeader::Control,
+            "global" => SfzHeader::Global,
+            "master" => SfzHeader::Master,
+            "group" => SfzHeader::Group,
+            "region" => SfzHeader::Region,
+            _ => SfzHeader::None,
+        };
+        match header {
+            SfzHeader::Global => {
+                self.global.clear();
+                self.master.clear();
+                self.group.clear();
+            }
+            SfzHeader::Master => {
+                self.master.clear();
+                self.group.clear();
+            }
+            SfzHeader::Group => {
+                self.group.clear();
+                self.group_i += 1;
+            }
+            _ => (),
+        }
+        self.header = Some(header);
+    }
+    fn opcode(&mut self, key: &str, value: &str) {
+        let opcodes = match self.header.unwrap_or(SfzHeader::None) {
+            SfzHeader::Control => &mut self.control,
+            SfzHeader::Global => &mut self.global,
+            SfzHeader::Master => &mut self.master,
+            SfzHeader::Group => &mut self.group,
+            SfzHeader::Region => &mut self.region,
+            SfzHeader::None => return,
+        };
+        opcodes.insert(key.to_string(), value.to_string());
+    }
+    fn end_region(&mut self) {
+        if self.header != Some(SfzHeader::Region) {
+            return;
+        }
+        let mut opcodes = self.global.clone();
+        opcodes.extend(self.master.clone());
+        opcodes.extend(self.group.clone());
+        opcodes.extend(self.region.drain());
+        self.regions.push(SfzRegionSpec { group: self.group_i, opcodes });
+    }
+    fn finish(mut self) -> SfzFile {
+        self.end_region();
+        SfzFile {
+            default_path: self.control.remove("default_path").unwrap_or_default(),
+            regions: self.regions,
+        }
+    }
+}
+
+/// Parse a midi note number or a note name like `c#4` (where `c4` is 60).
+fn parse_key(value: &str) -> Option<u8> {
+    if let Ok(key) = value.parse::<i32>() {
+        return Some(key.clamp(0, 127) as u8);
+    }
+    let value = value.to_ascii_lowercase();
+    let mut chars = value.chars();
+    let mut note = match chars.next()? {
+        'c' => 0, 'd' => 2, 'e' => 4, 'f' => 5, 'g' => 7, 'a' => 9, 'b' => 11,
+        _ => return None,
+    };
+    let mut octave = chars.as_str();
+    if let Some(rest) = octave.strip_prefix('#') {
+        note += 1;
+        octave = rest;
+    } else if let Some(rest) = octave.strip_prefix('b') {
+        note -= 1;
+        octave = rest;
+    }
+    let octave: i32 = octave.parse().ok()?;
+    Some((note + (octave + 1) * 12).clamp(0, 127) as u8)
+}
+
+#[derive(Clone, Copy)]
+pub enum SfzLoopMode {
+    NoLoop,
+    OneShot,
+    LoopContinuous,
+    LoopSustain,
+}
+
+#[derive(Clone)]
+pub struct SfzRegion {
+    pub sample: Arc<Sample>,
+    group: usize,
+
+    lokey: u8,
+    hikey: u8,
+    lovel: u8,
+    hivel: u8,
+    seq_length: u32,
+    seq_position: u32,
+
+    pub pitch_keycenter: u8,
+    /// `transpose` and `tune` together, in cents
+    pub tune: f32,
+    /// in dB
+    pub volume: f32,
+    /// `-100` (left) to `100` (right)
+    pub pan: f32,
+    /// start offset, in samples
+    pub offset: u32,
+    pub loop_mode: SfzLoopMode,
+    /// in samples, the end is inclusive like in the format
+    pub loop_start: u32,
+    pub loop_end: u32,
+}
+impl SfzRegion {
+    fn from_spec(spec: &SfzRegionSpec, sample: Arc<Sample>) -> Self {
+        let get = |key: &str| spec.opcodes.get(key).map(|v| v.as_str());
+        let get_f32 = |key: &str, default: f32| get(key).and_then(|v| v.parse().ok()).unwrap_or(default);
+        let get_u32 = |key: &str, default: u32| get(key).and_then(|v| v.parse().ok()).unwrap_or(default);
+        let get_key = |key: &str| get(key).and_then(parse_key);
+
+        let key = get_key("key");
+        let lokey = get_key("lokey").or(key).unwrap_or(0);
+        let hikey = get_key("hikey").or(key).unwrap_or(127);
+        let last_sample = (sample.len() as u32).saturating_sub(1);
+        let loop_end = get_u32("loop_end", get_u32("loopend", last_sample));
+        let loop_mode = match get("loop_mode").or(get("loopmode")) {
+            Some("one_shot") => SfzLoopMode::OneShot,
+            Some("loop_continuous") => SfzLoopMode::LoopContinuous,
+            Some("loop_sustain") => SfzLoopMode::LoopSustain,
+            Some(_) => SfzLoopMode::NoLoop,
+            // Files with loop points but no mode expect them to be used.
+            None if get("loop_start").or(get("loopstart")).is_some() => SfzLoopMode::LoopContinuous,
+            None => SfzLoopMode::NoLoop,
+        };
+
+        Self {
+            group: spec.group,
+
+            lokey,
+            hikey,
+            lovel: get_u32("lovel", 0).min(127) as u8,
+            hivel: get_u32("hivel", 127).min(127) as u8,
+            seq_length: get_u32("seq_length", 1).max(1),
+            seq_position: get_u32("seq_position", 1).max(1),
+
+            pitch_keycenter: get_key("pitch_keycenter").or(key).unwrap_or(60),
+            tune: get_f32("transpose", 0.0) * 100.0 + get_f32("tune", 0.0),
+            volume: get_f32("volume", 0.0),
+            pan: get_f32("pan", 0.0).clamp(-100.0, 100.0),
+            offset: get_u32("offset", 0),
+            loop_mode,
+            loop_start: get_u32("loop_start", get_u32("loopstart", 0)),
+            loop_end,
+
+            sample,
+        }
+    }
+    fn matches(&self, key: u8, velocity: u8) -> bool {
+        (self.lokey ..= self.hikey).contains(&key) && (self.lovel ..= self.hivel).contains(&velocity)
+    }
+}
+
+pub struct SfzInstrument {
+    regions: Vec<SfzRegion>,
+    /// notes played per group, for round-robin
+    round_robin: Vec<u32>,
+}
+impl SfzInstrument {
+    /// Load an .sfz file and its samples, also returning a hash of all their contents.
+    pub fn load(file_path: &Path) -> Option<(Self, u64)> {
+        let text = fs::read(file_path).ok()?;
+        let mut hash = Fnv1a64::new();
+        hash.write(&text);
+
+        let file = SfzFile::parse(&String::from_utf8_lossy(&text));
+        let dir = file_path.parent().unwrap_or(Path::new(""));
+
+        let mut samples: HashMap<PathBuf, Arc<Sample>> = HashMap::new();
+        let mut regions = vec![];
+        for spec in &file.regions {
+            let sample_path = match spec.opcodes.get("sample") {
+                Some(sample_path) => dir.join(&file.default_path).join(sample_path.replace('\\', "/")),
+                None => continue,
+            };
+            let sample = match samples.get(&sample_path) {
+                Some(sample) => sample.clone(),
+                None => {
+                    let bytes = match fs::read(&sample_path) {
+                        Ok(bytes) => bytes,
+                        Err(_) => continue,
+                    };
+                    hash.write(&bytes);
+                    let (header, data) = match Wav::from_reader_with_header(&mut Cursor::new(bytes)) {
+                        Some(wav) => wav,
+                        None => continue,
+                    };
+                    let sample = Arc::new(Sample::from_wav(&header, data));
+                    samples.insert(sample_path, sample.clone());
+                    sample
+                }
+            };
+            regions.push(SfzRegion::from_spec(spec, sample));
+        }
+
+        let groups = regions.iter().map(|region| region.group + 1).max().unwrap_or(0);
+        Some((Self { regions, round_robin: vec![0; groups] }, hash.finish()))
+    }
+
+    /// Pick the region a new note should play, advancing round-robin.
+    ///
+    /// Only the first matching region is played, layered regions are ignored.
+    pub fn pick_region(&mut self, id: &NoteId, velocity: f32) -> Option<SfzRegion> {
+        let velocity = (velocity * 127.0).round().clamp(0.0, 127.0) as u8;
+        let round_robin = &self.round_robin;
+        let region = self.regions.iter()
+            .filter(|region| region.matches(id.midi_note, velocity))
+            .find(|region| round_robin[region.group] % region.seq_length + 1 == region.seq_position)?
+            .clone();
+        self.round_robin[region.group] += 1;
+        Some(region)
+    }
+    /// The samples the regions play, each once.
+    pub fn into_samples(self) -> Vec<Arc<Sample>> {
+        let mut samples: Vec<Arc<Sample>> = vec![];
+        for region in self.regions {
+            if !samples.iter().any(|sample| Arc::ptr_eq(sample, &region.sample)) {
+                samples.push(region.sample);
+            }
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_are_inherited() {
+        let file = SfzFile::parse("
+            <control> default_path=samples/
+            <global> volume=-6
+            <group> lokey=60 hikey=72 // a comment
+            <region> sample=piano c4.wav pitch_keycenter=c4
+            <region> sample=piano e4.wav volume=-3
+        ");
+        assert_eq!(file.default_path, "samples/");
+        assert_eq!(file.regions.len(), 2);
+        assert_eq!(file.regions[0].opcodes["sample"], "piano c4.wav");
+        assert_eq!(file.regions[0].opcodes["volume"], "-6");
+        assert_eq!(file.regions[1].opcodes["volume"], "-3");
+        assert_eq!(file.regions[1].opcodes["hikey"], "72");
+    }
+
+    #[test]
+    fn note_names() {
+        assert_eq!(parse_key("c4"), Some(60));
+        assert_eq!(parse_key("C#4"), Some(61));
+        assert_eq!(parse_key("eb3"), Some(51));
+        assert_eq!(parse_key("69"), Some(69));
+    }
+}
@@ -0,0 +1,288 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use crossbeam::queue::ArrayQueue;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+
+use crate::util::{param_range::ParamRange, seeded_rng::SeededRng};
+
+use super::{params::Param, wavetable::{Wavetable, WavetableRaw}};
+
+/// Identifies the frame the cached spectrum was taken from: for each table, the table itself
+/// and the bits of its slice position and weight. Holding the tables means a new one can't
+/// be mistaken for a freed one that happened to live at the same address.
+pub struct FrameKey {
+    tables: [Arc<Wavetable>; 4],
+    slices: [u32; 4],
+    weights: [u32; 4],
+}
+impl FrameKey {
+    pub fn new(tables: [Arc<Wavetable>; 4], slices: [f32; 4], weights: [f32; 4]) -> Self {
+        Self { tables, slices: slices.map(f32::to_bits), weights: weights.map(f32::to_bits) }
+    }
+}
+impl PartialEq for FrameKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.tables.iter().zip(&other.tables).all(|(a, b)| Arc::ptr_eq(a, b))
+            && self.slices == other.slices
+            && self.weights == other.weights
+    }
+}
+
+/// FFT plans and working memory shared by every `SpectralProcessor`. The memory is allocated
+/// ahead of time by the background thread, so turning processing on never allocates.
+pub struct SpectralPool {
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    free: ArrayQueue<SpectralBuffers>,
+}
+impl Default for SpectralPool {
+    fn default() -> Self {
+        let mut planner = FftPlanner::new();
+        Self {
+            forward: planner.plan_fft_forward(SpectralProcessor::SIZE),
+            inverse: planner.plan_fft_inverse(SpectralProcessor::SIZE),
+            free: ArrayQueue::new(Self::CAPACITY),
+        }
+    }
+}
+impl SpectralPool {
+    /// Buffers kept ready, enough for a few voices to start with every oscillator processed.
+    const READY: usize = 12;
+    /// More than are ever taken at once, so giving buffers back doesn't fail.
+    const CAPACITY: usize = 256;
+    pub fn needs_refill(&self) -> bool {
+        self.free.len() < Self::READY
+    }
+    /// Allocate buffers until enough are ready. Call off the audio thread.
+    pub fn refill(&self) {
+        while self.needs_refill() {
+            if self.free.push(SpectralBuffers::new(self)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+pub struct SpectralSpec {
+    active: bool,
+    /// lowest and highest harmonic let through
+    low_cut: f32,
+    high_cut: f32,
+    /// moves harmonic `k` to `k * stretch`, leaving gaps in between
+    stretch: f32,
+    /// moves the spectral envelope without moving the harmonics
+    formant_shift: f32,
+    /// `-1.0` keeps only odd harmonics, `1.0` only even ones
+    odd_even: f32,
+    phase_random: f32,
+}
+impl SpectralSpec {
+    pub fn new(
+        low_cut: f32,
+        high_cut: f32,
+        stretch: f32,
+        formant_shift: f32,
+        odd_even: f32,
+        phase_random: f32,
+    ) -> Self {
+        Self { active: true, low_cut, high_cut, stretch, formant_shift, odd_even, phase_random }
+    }
+    /// Play the wavetable frames untouched.
+    pub fn none() -> Self {
+        Self { active: false, ..Self::new(1.0, SpectralProcessor::HARMONICS as f32, 1.0, 1.0, 0.0, 0.0) }
+    }
+}
+
+/// Working memory of a `SpectralProcessor`, taken from the `SpectralPool` while it is active.
+struct SpectralBuffers {
+    scratch: Vec<Complex<f32>>,
+    /// spectrum of the source frame, kept until the frame changes
+    input: Vec<Complex<f32>>,
+    input_key: Option<FrameKey>,
+    work: Vec<Complex<f32>>,
+    frame: Vec<f32>,
+    /// fixed per voice, so randomised phases don't change from block to block
+    random_phases: Vec<f32>,
+}
+impl SpectralBuffers {
+    fn new(pool: &SpectralPool) -> Self {
+        let scratch_len = pool.forward.get_inplace_scratch_len().max(pool.inverse.get_inplace_scratch_len());
+        Self {
+            scratch: vec![Complex::default(); scratch_len],
+            input: vec![Complex::default(); SpectralProcessor::SIZE],
+            input_key: None,
+            work: vec![Complex::default(); SpectralProcessor::SIZE],
+            frame: vec![0.0; SpectralProcessor::SIZE],
+            random_phases: vec![0.0; SpectralProcessor::HARMONICS + 1],
+        }
+    }
+    /// Start over for a processor seeded with `seed`.
+    fn reset(&mut self, seed: u64) {
+        self.input_key = None;
+        let mut rng = SeededRng::new(seed);
+        for phase in &mut self.random_phases {
+            *phase = rng.next_f32();
+        }
+    }
+}
+
+/// Reworks the harmonics of one wavetable frame each block, for an oscillator to play back.
+pub struct SpectralProcessor {
+    active: bool,
+    seed: u64,
+    pool: Arc<SpectralPool>,
+    buffers: Option<SpectralBuffers>,
+
+    pub low_cut: Param,
+    pub high_cut: Param,
+    pub stretch: Param,
+    pub formant_shift: Param,
+    pub odd_even: Param,
+    pub phase_random: Param,
+}
+
+impl SpectralProcessor {
+    const SIZE: usize = WavetableRaw::SIZE;
+    pub const HARMONICS: usize = Self::SIZE / 2;
+
+    pub fn rangeof_harmonic() -> ParamRange { ParamRange::exponential(1.0, Self::HARMONICS as f32) }
+    pub fn rangeof_stretch() -> ParamRange { ParamRange::exponential(0.25, 4.0) }
+    pub fn rangeof_formant_shift() -> ParamRange { ParamRange::exponential(0.25, 4.0) }
+    pub fn rangeof_odd_even() -> ParamRange { ParamRange::linear(-1.0, 1.0) }
+    pub fn rangeof_phase_random() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn new(spec: &SpectralSpec, pool: Arc<SpectralPool>, seed: u64) -> Self {
+        Self {
+            active: spec.active,
+            seed,
+            pool,
+            buffers: None,
+
+            low_cut: Param::new(spec.low_cut, Self::rangeof_harmonic()),
+            high_cut: Param::new(spec.high_cut, Self::rangeof_harmonic()),
+            stretch: Param::new(spec.stretch, Self::rangeof_stretch()),
+            formant_shift: Param::new(spec.formant_shift, Self::rangeof_formant_shift()),
+            odd_even: Param::new(spec.odd_even, Self::rangeof_odd_even()),
+            phase_random: Param::new(spec.phase_random, Self::rangeof_phase_random()),
+        }
+    }
+    pub fn update_spec(&mut self, spec: &SpectralSpec) {
+        self.active = spec.active;
+        self.low_cut.rebase(spec.low_cut);
+        self.high_cut.rebase(spec.high_cut);
+        self.stretch.rebase(spec.stretch);
+        self.formant_shift.rebase(spec.formant_shift);
+        self.odd_even.rebase(spec.odd_even);
+        self.phase_random.rebase(spec.phase_random);
+    }
+    /// Take working memory from the pool when processing turns on and give it back when it
+    /// turns off. Returns whether `block` and `sample` will process the frame.
+    pub fn prepare(&mut self) -> bool {
+        if !self.active {
+            self.give_back();
+        } else if self.buffers.is_none() {
+            if let Some(mut buffers) = self.pool.free.pop() {
+                buffers.reset(self.seed);
+                self.buffers = Some(buffers);
+            }
+        }
+        self.buffers.is_some()
+    }
+    fn give_back(&mut self) {
+        if let Some(buffers) = self.buffers.take() {
+            // Only fails if more are given back than the pool ever held; dropping is fine then.
+            let _ = self.pool.free.push(buffers);
+        }
+    }
+    /// Rebuild the frame to play this block, with parameters taken at its start.
+    ///
+    /// `fill` writes the source frame, and is only called when `key` differs from the last
+    /// block. Harmonics above `max_harmonic` are dropped so the frame does not alias.
+    pub fn block(
+        &mut self,
+        block_len: usize,
+        key: FrameKey,
+        fill: impl FnOnce(&mut [f32]),
+        max_harmonic: f32,
+    ) {
+        let low_cut = self.low_cut.take(block_len).first().copied().unwrap_or(1.0);
+        let high_cut = self.high_cut.take(block_len).first().copied().unwrap_or(1.0);
+        let stretch = self.stretch.take(block_len).first().copied().unwrap_or(1.0);
+        let formant_shift = self.formant_shift.take(block_len).first().copied().unwrap_or(1.0);
+        let odd_even = self.odd_even.take(block_len).first().copied().unwrap_or(0.0);
+        let phase_random = self.phase_random.take(block_len).first().copied().unwrap_or(0.0);
+
+        let buffers = match &mut self.buffers {
+            Some(buffers) => buffers,
+            None => return,
+        };
+        let plans = &self.pool;
+        if buffers.input_key.as_ref() != Some(&key) {
+            buffers.input_key = Some(key);
+            fill(&mut buffers.frame);
+            for (bin, sample) in buffers.input.iter_mut().zip(&buffers.frame) {
+                *bin = Complex::new(*sample, 0.0);
+            }
+            plans.forward.process_with_scratch(&mut buffers.input, &mut buffers.scratch);
+        }
+
+        let input = &buffers.input;
+        let magnitude_at = |pos: f32| {
+            let k = pos as usize;
+            if k >= Self::HARMONICS {
+                return 0.0;
+            }
+            let t = pos - k as f32;
+            input[k].norm() * (1.0 - t) + input[k + 1].norm() * t
+        };
+
+        buffers.work.fill(Complex::default());
+        for (k, bin) in input.iter().enumerate().take(Self::HARMONICS).skip(1) {
+            let target = (k as f32 * stretch).round() as usize;
+            if target >= Self::HARMONICS {
+                break;
+            }
+            let norm = bin.norm();
+            if target == 0 || norm < 1e-9 {
+                continue;
+            }
+            buffers.work[target] += bin * (magnitude_at(k as f32 / formant_shift) / norm);
+        }
+
+        for k in 1 .. Self::HARMONICS {
+            let number = k as f32;
+            let range_gain = (number - low_cut + 1.0).clamp(0.0, 1.0)
+                * (high_cut - number + 1.0).clamp(0.0, 1.0)
+                * (max_harmonic - number + 1.0).clamp(0.0, 1.0);
+            let odd_even_gain = if k % 2 == 1 {
+                (1.0 - odd_even).min(1.0)
+            } else {
+                (1.0 + odd_even).min(1.0)
+            };
+            let rotation = Complex::from_polar(1.0, buffers.random_phases[k] * phase_random * TAU);
+            let bin = buffers.work[k] * rotation * (range_gain * odd_even_gain);
+            buffers.work[k] = bin;
+            buffers.work[Self::SIZE - k] = bin.conj();
+        }
+        // No DC, and nothing at nyquist where the phase can't be kept.
+        buffers.work[0] = Complex::default();
+        buffers.work[Self::HARMONICS] = Complex::default();
+
+        plans.inverse.process_with_scratch(&mut buffers.work, &mut buffers.scratch);
+        let norm = 1.0 / Self::SIZE as f32;
+        for (sample, bin) in buffers.frame.iter_mut().zip(&buffers.work) {
+            *sample = bin.re * norm;
+        }
+    }
+    /// Read the processed frame at `phase`.
+    pub fn sample(&self, phase: f32) -> f32 {
+        match &self.buffers {
+            Some(buffers) => buffers.frame[((phase * Self::SIZE as f32) as usize).min(Self::SIZE - 1)],
+            None => 0.0,
+        }
+    }
+}
+impl Drop for SpectralProcessor {
+    fn drop(&mut self) {
+        self.give_back();
+    }
+}
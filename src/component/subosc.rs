@@ -1,18 +1,61 @@
 use crate::util::{simple_waveforms::SimpleWaveform, increment_mod::increment_phase, param_range::ParamRange};
 
-use super::params::{ParamSource, ParamPolarity, Param};
+use super::{params::{ParamSource, ParamPolarity, Param}, sync::SyncMaster, phase_mode::PhaseMode, tuning::{TuningSpec, Tuning}};
 
+/// A sync master that can also hand out its phase, for a sub to follow.
+pub trait PhaseMaster: SyncMaster {
+    /// Phase at each sample, before the step any wrap in `sync_events` comes from.
+    fn phases(&self) -> &Vec<f32>;
+}
+
+#[derive(Clone, Copy)]
+pub enum SubLockSource {
+    OscP,
+    Osc(usize),
+}
+
+#[derive(Clone, Copy)]
+pub enum SubDivide {
+    One,
+    Two,
+    Four,
+}
+impl SubDivide {
+    fn factor(self) -> u32 {
+        match self {
+            Self::One => 1,
+            Self::Two => 2,
+            Self::Four => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SubMode {
+    /// Runs at its own phase, following the played note.
+    Free,
+    /// Follows the centre unison voice of a wavetable oscillator, `divide` times slower, so it
+    /// never drifts against it.
+    Locked { source: SubLockSource, divide: SubDivide },
+}
 
 pub struct SubOscillatorSpec {
-    freq_off: f32,
+    tuning: TuningSpec,
     waveform: SimpleWaveform,
+    phase: PhaseMode,
+    mode: SubMode,
+    /// part of the cycle a `SQUARE` spends low, `0.5` for a square
+    pulse_width: f32,
 }
 impl SubOscillatorSpec {
     pub fn new(
-        freq_off: f32,
+        tuning: TuningSpec,
         waveform: SimpleWaveform,
+        phase: PhaseMode,
+        mode: SubMode,
+        pulse_width: f32,
     ) -> Self {
-        Self { freq_off, waveform }
+        Self { tuning, waveform, phase, mode, pulse_width }
     }
 }
 
@@ -22,32 +65,117 @@ pub struct SubOscillator {
     buffer: Vec<f32>,
     spec: SubOscillatorSpec,
     phase: f32,
+    /// when the note started on the global clock, in seconds
+    note_time: f64,
+    started: bool,
+    sync_out: Vec<Option<f32>>,
+    /// master wraps counted since the note started, modulo the division
+    locked_count: u32,
+    locked_phases: Vec<f32>,
+    locked_events: Vec<Option<f32>>,
 
+    pub tuning: Tuning,
     pub freq: Param,
+    pub pulse_width: Param,
 }
 
 impl SubOscillator {
     pub fn rangeof_freq() -> ParamRange { ParamRange::exponential(0.5, 20000.0) }
+    pub fn rangeof_pulse_width() -> ParamRange { ParamRange::linear(0.01, 0.99) }
     pub fn new(sample_rate: f32, spec: SubOscillatorSpec) -> Self {
         Self {
             sample_rate,
             buffer: vec![],
-            freq: Param::new(spec.freq_off, Self::rangeof_freq()),
+            tuning: Tuning::new(&spec.tuning),
+            freq: Param::new(spec.tuning.freq_off(), Self::rangeof_freq()),
+            pulse_width: Param::new(spec.pulse_width, Self::rangeof_pulse_width()),
+            locked_count: 0,
+            locked_phases: vec![],
+            locked_events: vec![],
             spec,
             phase: 0.0,
+            note_time: 0.0,
+            started: false,
+            sync_out: vec![],
         }
     }
     pub fn update_spec(&mut self, spec: SubOscillatorSpec) {
-        self.freq.rebase(spec.freq_off);
+        self.freq.rebase(spec.tuning.freq_off());
+        self.tuning.update_spec(&spec.tuning);
+        self.spec.phase = spec.phase;
+        self.spec.mode = spec.mode;
+        self.pulse_width.rebase(spec.pulse_width);
+    }
+    /// The oscillator this follows, if it is locked to one.
+    pub fn lock_source(&self) -> Option<SubLockSource> {
+        match self.spec.mode {
+            SubMode::Free => None,
+            SubMode::Locked { source, .. } => Some(source),
+        }
+    }
+    /// Take the phase of the oscillator this is locked to, once it has rendered this block.
+    pub fn send_lock<T: PhaseMaster>(&mut self, master: &T) {
+        self.locked_phases.clear();
+        self.locked_phases.extend_from_slice(master.phases());
+        self.locked_events.clear();
+        self.locked_events.extend_from_slice(master.sync_events());
+    }
+    fn shape(waveform: &SimpleWaveform, phase: f32, pulse_width: f32) -> f32 {
+        match waveform {
+            SimpleWaveform::SQUARE => if phase >= pulse_width { 1.0 } else { -1.0 },
+            _ => waveform.sample(phase),
+        }
+    }
+    /// Set when the note started on the global clock, for free-running phase.
+    pub fn set_note_time(&mut self, note_time: f64) {
+        self.note_time = note_time;
     }
     pub fn block(&mut self, trigger_at: usize, block_len: usize) {
         self.buffer.clear();
+        self.sync_out.clear();
         let freq = self.freq.take(block_len);
+        let pulse_width = self.pulse_width.take(block_len);
+        let waveform = &self.spec.waveform;
+        if let SubMode::Locked { divide, .. } = self.spec.mode {
+            let divide = divide.factor();
+            // Nothing was sent if the master didn't render, stay silent rather than drift.
+            let sent = self.locked_phases.len() == block_len && self.locked_events.len() == block_len;
+            for (i, pulse_width) in pulse_width.iter().enumerate() {
+                if i == trigger_at && !self.started {
+                    self.started = true;
+                    self.locked_count = 0;
+                }
+                if !sent || !self.started {
+                    self.buffer.push(0.0);
+                    self.sync_out.push(None);
+                    continue;
+                }
+                self.phase = (self.locked_count as f32 + self.locked_phases[i]) / divide as f32;
+                self.buffer.push(Self::shape(waveform, self.phase, *pulse_width));
+                let mut sync_event = None;
+                if let Some(frac) = self.locked_events[i] {
+                    self.locked_count = (self.locked_count + 1) % divide;
+                    if self.locked_count == 0 {
+                        sync_event = Some(frac);
+                    }
+                }
+                self.sync_out.push(sync_event);
+            }
+            self.locked_phases.clear();
+            self.locked_events.clear();
+            return;
+        }
         for i in 0 .. block_len {
-            self.buffer.push(self.spec.waveform.sample(self.phase));
-            if i >= trigger_at {
-                increment_phase(&mut self.phase, self.sample_rate, freq[i]);
+            if i == trigger_at && !self.started {
+                self.started = true;
+                self.phase = self.spec.phase.start_phase(freq[i], self.note_time);
+            }
+            self.buffer.push(Self::shape(waveform, self.phase, pulse_width[i]));
+            let mut sync_event = None;
+            if i >= trigger_at && increment_phase(&mut self.phase, self.sample_rate, freq[i]) {
+                sync_event = Some((self.phase * self.sample_rate / freq[i]).min(1.0));
             }
+            self.sync_out.push(sync_event);
         }
     }
 }
@@ -56,4 +184,9 @@ impl ParamSource for SubOscillator {
     fn source_param_buffer(&self) -> &Vec<f32> {
         &self.buffer
     }
+}
+impl SyncMaster for SubOscillator {
+    fn sync_events(&self) -> &Vec<Option<f32>> {
+        &self.sync_out
+    }
 }
\ No newline at end of file
@@ -0,0 +1,90 @@
+/// Something whose phase wraps can act as a sync master.
+///
+/// Each entry in the buffer is `Some(frac)` if the phase wrapped between that sample and the
+/// next, where `frac` is the part of the sample period that had already passed the wrap.
+pub trait SyncMaster {
+    fn sync_events(&self) -> &Vec<Option<f32>>;
+}
+
+#[derive(Clone, Copy)]
+pub enum SyncMode {
+    None,
+    /// Reset the slave's phases when the master wraps.
+    Hard,
+    /// Reverse the slave's direction when the master wraps.
+    Soft,
+}
+
+#[derive(Clone, Copy)]
+pub enum SyncSource {
+    OscP,
+    /// one of the voice's main oscillators, by index
+    Osc(usize),
+    Sub,
+}
+
+pub struct SyncSpec {
+    mode: SyncMode,
+    source: SyncSource,
+}
+impl SyncSpec {
+    pub fn new(
+        mode: SyncMode,
+        source: SyncSource,
+    ) -> Self {
+        Self { mode, source }
+    }
+    pub fn none() -> Self {
+        Self::new(SyncMode::None, SyncSource::OscP)
+    }
+}
+
+pub struct SyncInput {
+    pub mode: SyncMode,
+    pub source: SyncSource,
+    buffer: Vec<Option<f32>>,
+    sent: bool,
+}
+impl SyncInput {
+    pub fn new(spec: &SyncSpec) -> Self {
+        Self {
+            mode: spec.mode,
+            source: spec.source,
+            buffer: vec![],
+            sent: false,
+        }
+    }
+    pub fn update_spec(&mut self, spec: &SyncSpec) {
+        self.mode = spec.mode;
+        self.source = spec.source;
+    }
+    pub fn is_active(&self) -> bool {
+        !matches!(self.mode, SyncMode::None)
+    }
+
+    pub fn send<T : SyncMaster>(&mut self, master: &T) {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(master.sync_events());
+        self.sent = true;
+    }
+    /// Take the master's wrap events, if they were sent for this block.
+    pub fn take(&mut self, block_len: usize) -> Option<&Vec<Option<f32>>> {
+        let sent = std::mem::replace(&mut self.sent, false);
+        if sent && self.is_active() && self.buffer.len() == block_len {
+            Some(&self.buffer)
+        } else {
+            None
+        }
+    }
+}
+
+/// PolyBLEP residual for the sample just before a unit step, `frac` being the part of the
+/// sample period after the step.
+pub fn polyblep_before(frac: f32) -> f32 {
+    frac * frac * 0.5
+}
+/// PolyBLEP residual for the sample just after a unit step.
+pub fn polyblep_after(frac: f32) -> f32 {
+    let p = 1.0 - frac;
+    -p * p * 0.5
+}
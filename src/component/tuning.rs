@@ -0,0 +1,80 @@
+use crate::util::param_range::ParamRange;
+
+use super::params::ParamImmut;
+
+/// Frequency of middle C, which keytracking scales around.
+const KEYTRACK_CENTRE: f32 = 261.6256;
+
+pub struct TuningSpec {
+    octave: f32,
+    semitone: f32,
+    /// in cents
+    fine: f32,
+    /// `1.0` follows the played note, `0.0` stays at middle C
+    keytrack: f32,
+    /// play this frequency, in Hz, whatever the note
+    fixed: Option<f32>,
+    /// added to the frequency after tuning, in Hz
+    freq_off: f32,
+}
+impl TuningSpec {
+    pub fn new(
+        octave: f32,
+        semitone: f32,
+        fine: f32,
+        keytrack: f32,
+        fixed: Option<f32>,
+        freq_off: f32,
+    ) -> Self {
+        Self { octave, semitone, fine, keytrack, fixed, freq_off }
+    }
+    /// Plays the note as is, offset by `freq_off`.
+    pub fn keytracked(freq_off: f32) -> Self {
+        Self::new(0.0, 0.0, 0.0, 1.0, None, freq_off)
+    }
+    pub fn freq_off(&self) -> f32 {
+        self.freq_off
+    }
+}
+
+/// Coarse and fine pitch of a component, applied to the note before it is sent to its `freq`.
+pub struct Tuning {
+    pub octave: ParamImmut,
+    pub semitone: ParamImmut,
+    pub fine: ParamImmut,
+    pub keytrack: ParamImmut,
+    fixed: Option<f32>,
+}
+impl Tuning {
+    pub fn rangeof_octave() -> ParamRange { ParamRange::linear(-4.0, 4.0) }
+    pub fn rangeof_semitone() -> ParamRange { ParamRange::linear(-12.0, 12.0) }
+    pub fn rangeof_fine() -> ParamRange { ParamRange::linear(-100.0, 100.0) }
+    pub fn rangeof_keytrack() -> ParamRange { ParamRange::linear(0.0, 2.0) }
+    pub fn new(spec: &TuningSpec) -> Self {
+        Self {
+            octave: ParamImmut::new(spec.octave, Self::rangeof_octave()),
+            semitone: ParamImmut::new(spec.semitone, Self::rangeof_semitone()),
+            fine: ParamImmut::new(spec.fine, Self::rangeof_fine()),
+            keytrack: ParamImmut::new(spec.keytrack, Self::rangeof_keytrack()),
+            fixed: spec.fixed,
+        }
+    }
+    pub fn update_spec(&mut self, spec: &TuningSpec) {
+        self.octave.rebase(spec.octave);
+        self.semitone.rebase(spec.semitone);
+        self.fine.rebase(spec.fine);
+        self.keytrack.rebase(spec.keytrack);
+        self.fixed = spec.fixed;
+    }
+    /// Frequency to play for a note at `note_freq`.
+    pub fn apply(&self, note_freq: f32) -> f32 {
+        let base = match self.fixed {
+            Some(fixed) => fixed,
+            None => KEYTRACK_CENTRE * (note_freq / KEYTRACK_CENTRE).powf(self.keytrack.read()),
+        };
+        let octaves = self.octave.read().round()
+            + self.semitone.read().round() / 12.0
+            + self.fine.read() / 1200.0;
+        base * octaves.exp2()
+    }
+}
@@ -0,0 +1,56 @@
+use crate::util::param_range::ParamRange;
+
+use super::params::Param;
+
+/// Corners of the X/Y plane the four tables sit at, in table order.
+const CORNERS: [(f32, f32); 4] = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)];
+
+pub struct VectorSpec {
+    x: f32,
+    y: f32,
+    /// slice position of each table
+    slices: [f32; 4],
+}
+impl VectorSpec {
+    pub fn new(
+        x: f32,
+        y: f32,
+        slices: [f32; 4],
+    ) -> Self {
+        Self { x, y, slices }
+    }
+    pub fn neutral() -> Self {
+        Self::new(0.0, 0.0, [0.5; 4])
+    }
+}
+
+/// Position on the plane between four wavetables, and where each table is scanned.
+pub struct VectorMorph {
+    pub x: Param,
+    pub y: Param,
+    pub slices: [Param; 4],
+}
+impl VectorMorph {
+    pub fn rangeof_axis() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn rangeof_slice() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    pub fn new(spec: &VectorSpec) -> Self {
+        Self {
+            x: Param::new(spec.x, Self::rangeof_axis()),
+            y: Param::new(spec.y, Self::rangeof_axis()),
+            slices: spec.slices.map(|slice| Param::new(slice, Self::rangeof_slice())),
+        }
+    }
+    pub fn update_spec(&mut self, spec: &VectorSpec) {
+        self.x.rebase(spec.x);
+        self.y.rebase(spec.y);
+        for (slice, value) in self.slices.iter_mut().zip(spec.slices) {
+            slice.rebase(value);
+        }
+    }
+    /// Gain of each table at `(x, y)`, always summing to one.
+    pub fn weights(x: f32, y: f32) -> [f32; 4] {
+        CORNERS.map(|(cx, cy)| {
+            (1.0 - (x - cx).abs()).clamp(0.0, 1.0) * (1.0 - (y - cy).abs()).clamp(0.0, 1.0)
+        })
+    }
+}
@@ -24,6 +24,12 @@ impl WavetableRaw {
         )]
     }
 
+    /// One whole cycle, at the slice nearest to `slice`.
+    pub fn frame(&self, slice: f32) -> &[f32] {
+        let start = Self::index(remap_index(slice, Self::SIZE), 0);
+        &self.data[start .. start + Self::SIZE]
+    }
+
     fn index(slice: usize, sample: usize) -> usize {
         slice * Self::SIZE + sample
     }
@@ -165,12 +171,15 @@ impl Default for Wavetable {
 pub struct Wav {}
 impl Wav {
     pub fn from_filepath(file_path: &Path) -> Option<Vec<f32>> {
+        Self::from_filepath_with_header(file_path).map(|(_, data)| data)
+    }
+    pub fn from_filepath_with_header(file_path: &Path) -> Option<(wav::Header, Vec<f32>)> {
         let mut p = if let Some(p) = File::open(file_path).ok() {
             p
         } else {
             return None;
         };
-        Self::from_reader(&mut p)
+        Self::from_reader_with_header(&mut p)
     }
 
     pub fn from_bytes<const L: usize>(bytes: &[u8; L]) -> Option<Vec<f32>> {
@@ -178,6 +187,14 @@ impl Wav {
     }
 
     pub fn from_reader<R>(r: &mut R) -> Option<Vec<f32>>
+    where
+        R: Read + io::Seek,
+    {
+        Self::from_reader_with_header(r).map(|(_, data)| data)
+    }
+
+    /// Like `from_reader`, but also hands back the header for the channel count and sample rate.
+    pub fn from_reader_with_header<R>(r: &mut R) -> Option<(wav::Header, Vec<f32>)>
     where
         R: Read + io::Seek,
     {
@@ -223,6 +240,6 @@ impl Wav {
             _ => return None,
         };
 
-        Some(data)
+        Some((wav_hdr, data))
     }
 }
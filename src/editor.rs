@@ -10,29 +10,33 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use crate::common_data::WAVETABLE_SLOTS;
+use crate::component::lfo::LFOS;
 use crate::params::TestParams;
 use crate::state::text::TextState;
 
+use self::lfo_shape::LFOShapeView;
+
+mod lfo_shape;
+
 
 #[derive(Lens)]
 struct Data {
     params: Arc<TestParams>,
     peak_meter: Arc<AtomicF32>,
-    t: Arc<TextState>,
-    t_id: Arc<AtomicI64>,
+    sample_path: Arc<TextState>,
+    sfz_path: Arc<TextState>,
 }
 
 impl Model for Data {}
 
 // Makes sense to also define this here, makes it a bit easier to keep track of
 pub(crate) fn default_state() -> Arc<ViziaState> {
-    ViziaState::new(|| (200, 200))
+    ViziaState::new(|| (300, 600))
 }
 
 pub(crate) fn create(
     params: Arc<TestParams>,
-    t: Arc<TextState>,
-    t_id: Arc<AtomicI64>,
     peak_meter: Arc<AtomicF32>,
     editor_state: Arc<ViziaState>,
 ) -> Option<Box<dyn Editor>> {
@@ -43,53 +47,42 @@ pub(crate) fn create(
         Data {
             params: params.clone(),
             peak_meter: peak_meter.clone(),
-            t: t.clone(),
-            t_id: t_id.clone(),
+            sample_path: params.sample_path.clone(),
+            sfz_path: params.sfz_path.clone(),
         }
         .build(cx);
 
         ResizeHandle::new(cx);
 
         VStack::new(cx, |cx| {
-            {
-                let t = t.clone();
-                let t_id = t_id.clone();
-                Button::new(cx,  move |_| {
-                    let t = t.clone();
-                    let t_id = t_id.clone();
-                    thread::spawn(move || {
-                        let mut loc = t.get_v();
-                        let last_loc_exists = Path::new(&loc).exists();
-                        if !last_loc_exists {
-                            loc = "~/Desktop".to_string();
-                        }
-                        
-
-                        let path = FileDialog::new()
-                            .set_location(&loc)
-                            .add_filter(".WAV Image :3c", &["wav"])
-                            .show_open_single_file()
-                            .ok().unwrap_or(None);
-    
-                        if let Some(path) = path {
-                            let path = path.as_path().to_str().unwrap_or_default().to_string();
-                            t.set_v(path.to_string());
-                            t_id.store(rand::random(), Ordering::Relaxed);
-                        }
-                    });
-                }, |cx| {
-                    Label::new(cx, Data::t.map(|p| {
-                        let loc = p.get_v();
-                        let loc = Path::new(&loc);
-                        let default_loc = "<default>".to_string();
-                        return if !loc.is_file() {
-                            default_loc
-                        } else if let Some(n) = loc.file_name() { if let Some(n) = n.to_str() {
-                            n.to_string()
-                        } else { default_loc } }
-                        else { default_loc }
-                    }))
-                });
+            for slot in 0 .. WAVETABLE_SLOTS {
+                file_button(
+                    cx,
+                    params.wavetables[slot].path.clone(),
+                    params.wavetables[slot].path_id.clone(),
+                    Data::params.map(move |params| params.wavetables[slot].path.clone()),
+                    WAV_FILTER,
+                );
+            }
+            file_button(
+                cx,
+                params.sample_path.clone(),
+                params.sample_path_id.clone(),
+                Data::sample_path,
+                WAV_FILTER,
+            );
+            file_button(
+                cx,
+                params.sfz_path.clone(),
+                params.sfz_path_id.clone(),
+                Data::sfz_path,
+                (".SFZ Instrument", &["sfz"]),
+            );
+
+            for slot in 0 .. LFOS {
+                LFOShapeView::new(cx, params.lfo_shapes.clone(), params.lfo_shapes_generation.clone(), slot)
+                    .width(Pixels(240.0))
+                    .height(Pixels(60.0));
             }
 
             Label::new(cx, "Gain GUI")
@@ -118,3 +111,55 @@ pub(crate) fn create(
         .child_right(Stretch(1.0));
     })
 }
+
+type FileFilter = (&'static str, &'static [&'static str]);
+const WAV_FILTER: FileFilter = (".WAV Image :3c", &["wav"]);
+
+/// Button that asks for a file on a background thread, storing the path in `t` and bumping
+/// `t_id` so the plugin reloads it.
+fn file_button<L>(
+    cx: &mut Context,
+    t: Arc<TextState>,
+    t_id: Arc<AtomicI64>,
+    lens: L,
+    filter: FileFilter,
+) where
+    L: Lens<Target = Arc<TextState>>,
+{
+    Button::new(cx, move |_| {
+        let t = t.clone();
+        let t_id = t_id.clone();
+        thread::spawn(move || {
+            let mut loc = t.get_v();
+            let last_loc_exists = Path::new(&loc).exists();
+            if !last_loc_exists {
+                loc = "~/Desktop".to_string();
+            }
+
+
+            let path = FileDialog::new()
+                .set_location(&loc)
+                .add_filter(filter.0, filter.1)
+                .show_open_single_file()
+                .ok().unwrap_or(None);
+
+            if let Some(path) = path {
+                let path = path.as_path().to_str().unwrap_or_default().to_string();
+                t.set_v(path.to_string());
+                t_id.store(rand::random(), Ordering::Relaxed);
+            }
+        });
+    }, move |cx| {
+        Label::new(cx, lens.map(|p| {
+            let loc = p.get_v();
+            let loc = Path::new(&loc);
+            let default_loc = "<default>".to_string();
+            return if !loc.is_file() {
+                default_loc
+            } else if let Some(n) = loc.file_name() { if let Some(n) = n.to_str() {
+                n.to_string()
+            } else { default_loc } }
+            else { default_loc }
+        }))
+    });
+}
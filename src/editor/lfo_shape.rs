@@ -0,0 +1,182 @@
+use nih_plug_vizia::vizia::prelude::*;
+use nih_plug_vizia::vizia::vg;
+use std::sync::{Arc, RwLock, atomic::{AtomicU64, Ordering}};
+
+use crate::component::{lfo::LFOS, lfo_shape::{LFOShape, LFOShapePreset, LFOPoint}};
+
+enum LFOShapeEvent {
+    NextPreset,
+}
+
+/// Draws one of the plugin's LFO shapes and lets it be edited. Drag a point to move it, click
+/// elsewhere to add one, right click a point to remove it, and scroll over a segment to bend it.
+pub struct LFOShapeView {
+    shapes: Arc<RwLock<[LFOShape; LFOS]>>,
+    /// bumped after every edit, so the plugin renders the shapes again
+    generation: Arc<AtomicU64>,
+    slot: usize,
+    /// index into `LFOShapePreset::ALL` of the preset loaded last
+    preset: usize,
+    dragging: Option<usize>,
+}
+
+impl LFOShapeView {
+    /// How close to a point, in pixels, the cursor has to be to pick it.
+    const PICK_DISTANCE: f32 = 8.0;
+    const CURVE_STEP: f32 = 0.5;
+
+    pub fn new(
+        cx: &mut Context,
+        shapes: Arc<RwLock<[LFOShape; LFOS]>>,
+        generation: Arc<AtomicU64>,
+        slot: usize,
+    ) -> Handle<Self> {
+        Self { shapes, generation, slot, preset: LFOShapePreset::ALL.len() - 1, dragging: None }
+            .build(cx, |cx| {
+                Button::new(
+                    cx,
+                    |cx| cx.emit(LFOShapeEvent::NextPreset),
+                    |cx| Label::new(cx, "Preset"),
+                )
+                .left(Stretch(1.0));
+            })
+    }
+
+    /// Cursor position as a point in the shape, `x` from `0.0` to `1.0` and `y` from `-1.0` to
+    /// `1.0`.
+    fn cursor_point(cx: &EventContext) -> (f32, f32) {
+        let bounds = cx.bounds();
+        let x = (cx.mouse().cursorx - bounds.x) / bounds.w;
+        let y = 1.0 - (cx.mouse().cursory - bounds.y) / bounds.h * 2.0;
+        (x.clamp(0.0, 1.0), y.clamp(-1.0, 1.0))
+    }
+    /// Let the plugin know the shape was edited, and redraw it.
+    fn changed(&self, cx: &mut EventContext) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        cx.needs_redraw();
+    }
+    /// Point of `shape` under the cursor, if any.
+    fn pick(cx: &EventContext, shape: &LFOShape) -> Option<usize> {
+        let bounds = cx.bounds();
+        let (x, y) = Self::cursor_point(cx);
+        shape.points.iter()
+            .map(|point| {
+                let dx = (point.x - x) * bounds.w;
+                let dy = (point.y - y) * bounds.h / 2.0;
+                (dx * dx + dy * dy).sqrt()
+            })
+            .enumerate()
+            .filter(|(_, distance)| *distance < Self::PICK_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+}
+
+impl View for LFOShapeView {
+    fn element(&self) -> Option<&'static str> {
+        Some("lfo-shape")
+    }
+
+    fn event(&mut self, cx: &mut EventContext, event: &mut Event) {
+        event.map(|shape_event, meta| match shape_event {
+            LFOShapeEvent::NextPreset => {
+                self.preset = (self.preset + 1) % LFOShapePreset::ALL.len();
+                self.shapes.write().unwrap()[self.slot] = LFOShapePreset::ALL[self.preset].shape();
+                self.changed(cx);
+                meta.consume();
+            }
+        });
+        event.map(|window_event, meta| match window_event {
+            WindowEvent::MouseDown(MouseButton::Left) => {
+                let mut shapes = self.shapes.write().unwrap();
+                let shape = &mut shapes[self.slot];
+                self.dragging = Self::pick(cx, shape).or_else(|| {
+                    let (x, y) = Self::cursor_point(cx);
+                    let i = shape.points.iter().position(|point| point.x > x).unwrap_or(shape.points.len());
+                    shape.points.insert(i, LFOPoint::new(x, y, 0.0));
+                    Some(i)
+                });
+                cx.capture();
+                self.changed(cx);
+                meta.consume();
+            }
+            WindowEvent::MouseDown(MouseButton::Right) => {
+                let mut shapes = self.shapes.write().unwrap();
+                let shape = &mut shapes[self.slot];
+                if let Some(i) = Self::pick(cx, shape) {
+                    // A shape needs somewhere to go.
+                    if shape.points.len() > 2 {
+                        shape.points.remove(i);
+                        self.changed(cx);
+                    }
+                }
+                meta.consume();
+            }
+            WindowEvent::MouseMove(_, _) => {
+                if let Some(i) = self.dragging {
+                    let (x, y) = Self::cursor_point(cx);
+                    let mut shapes = self.shapes.write().unwrap();
+                    let points = &mut shapes[self.slot].points;
+                    // Stay between the neighbours, so the points stay in order.
+                    let min = if i > 0 { points[i - 1].x } else { 0.0 };
+                    let max = points.get(i + 1).map_or(1.0, |point| point.x);
+                    points[i].x = x.clamp(min, max);
+                    points[i].y = y;
+                    self.changed(cx);
+                }
+            }
+            WindowEvent::MouseUp(MouseButton::Left) => {
+                if self.dragging.take().is_some() {
+                    cx.release();
+                    meta.consume();
+                }
+            }
+            WindowEvent::MouseScroll(_, delta) => {
+                let (x, _) = Self::cursor_point(cx);
+                let mut shapes = self.shapes.write().unwrap();
+                let points = &mut shapes[self.slot].points;
+                // The segment under the cursor bends by the curve of the point it ends at.
+                let i = points.iter().position(|point| point.x >= x).unwrap_or(0);
+                if let Some(point) = points.get_mut(i) {
+                    point.curve = (point.curve + delta * Self::CURVE_STEP).clamp(-20.0, 20.0);
+                    self.changed(cx);
+                }
+                meta.consume();
+            }
+            _ => {}
+        });
+    }
+
+    fn draw(&self, cx: &mut DrawContext, canvas: &mut Canvas) {
+        let bounds = cx.bounds();
+        if bounds.w == 0.0 || bounds.h == 0.0 {
+            return;
+        }
+        let shapes = self.shapes.read().unwrap();
+        let shape = &shapes[self.slot];
+        let to_y = |value: f32| bounds.y + (1.0 - value) / 2.0 * bounds.h;
+
+        let mut background = vg::Path::new();
+        background.rect(bounds.x, bounds.y, bounds.w, bounds.h);
+        canvas.fill_path(&mut background, &vg::Paint::color(vg::Color::rgbf(0.1, 0.1, 0.12)));
+
+        let mut line = vg::Path::new();
+        let steps = bounds.w.ceil() as usize;
+        for step in 0 ..= steps {
+            let x = step as f32 / steps as f32;
+            let y = to_y(shape.value(x));
+            if step == 0 {
+                line.move_to(bounds.x, y);
+            } else {
+                line.line_to(bounds.x + x * bounds.w, y);
+            }
+        }
+        canvas.stroke_path(&mut line, &vg::Paint::color(vg::Color::rgbf(0.9, 0.9, 0.9)).with_line_width(1.5));
+
+        let mut handles = vg::Path::new();
+        for point in &shape.points {
+            handles.circle(bounds.x + point.x * bounds.w, to_y(point.y), 3.0);
+        }
+        canvas.fill_path(&mut handles, &vg::Paint::color(vg::Color::rgbf(0.4, 0.7, 1.0)));
+    }
+}
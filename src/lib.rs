@@ -5,7 +5,7 @@ use std::{
 };
 
 use atomic_float::AtomicF32;
-use common_data::{CommonDataRef, CommonData};
+use common_data::{CommonDataRef, CommonData, Transport, WAVETABLE_SLOTS};
 use nih_plug::{nih_export_vst3, prelude::*};
 
 mod component;
@@ -17,66 +17,149 @@ mod state;
 mod util;
 mod common_data;
 
-use component::wavetable::{Wav, Wavetable};
+use component::{wavetable::{Wav, Wavetable}, sampler::Sample, sfz::SfzInstrument, lfo::{LFO, LFOScope, LFOS}, lfo_shape::LFOShapeTable, spectral::SpectralPool};
 use note::{id::NoteId, *};
-use params::TestParams;
+use params::{TestParams, VoiceMode};
 
 const MAX_POLYPHONY: usize = 16;
 
 const MIDI_SPEC_CHANNEL_COUNT: usize = 16;
 
+/// Work too slow for the audio thread.
+enum Task {
+    /// Load the sample at `TestParams::sample_path` into `CommonData`.
+    LoadSample,
+    /// Load the instrument at `TestParams::sfz_path` into `CommonData`.
+    LoadSfz,
+    /// Load the wavetable at `TestParams::wavetables[slot]` into `CommonData`.
+    LoadWavetable(usize),
+    /// Free the retired data no voice holds any more.
+    FreeRetired,
+    /// Render `TestParams::lfo_shapes` into `CommonData`.
+    RenderLFOShapes,
+    /// Copy `TestParams::mseg` into `CommonData`.
+    CopyMseg,
+    /// Allocate spectral buffers for oscillators to take.
+    FillSpectralPool,
+}
+
 struct TestPlugin {
     params: Arc<TestParams>,
     sample_rate: f32,
+    process_mode: ProcessMode,
 
     voices: Vec<Voice>,
+    /// keys down, oldest first, with their velocity, for a mono voice to go back to when the
+    /// key it plays is let go
+    held_notes: Vec<(NoteId, f32)>,
+    /// LFOs every voice with the matching LFO set to `LFOScope::Global` reads
+    global_lfos: [LFO; LFOS],
     channel_tunings: [f32; MIDI_SPEC_CHANNEL_COUNT],
     channel_aftertouch: [f32; MIDI_SPEC_CHANNEL_COUNT],
 
     peak_meter: Arc<AtomicF32>,
 
     data: CommonDataRef,
-    last_rel_id: i64,
+    last_wavetable_path_ids: [i64; WAVETABLE_SLOTS],
+    last_sample_path_id: i64,
+    last_sfz_path_id: i64,
+    last_lfo_shapes_generation: u64,
 }
 impl TestPlugin {
-    fn update_wave(&mut self) {
-        let rel_id = self.params.rel_id.load(Ordering::Relaxed);
-        if rel_id == self.last_rel_id {
-            return;
+    /// Slots whose wavetable needs to be (re)loaded.
+    fn wavetables_changed(&mut self) -> impl Iterator<Item = usize> {
+        let mut changed = [false; WAVETABLE_SLOTS];
+        for (slot, last_path_id) in self.last_wavetable_path_ids.iter_mut().enumerate() {
+            let path_id = self.params.wavetables[slot].path_id.load(Ordering::Relaxed);
+            if path_id != *last_path_id {
+                *last_path_id = path_id;
+                changed[slot] = true;
+            }
+        }
+        (0 .. WAVETABLE_SLOTS).filter(move |&slot| changed[slot])
+    }
+
+    /// Whether the sample needs to be (re)loaded.
+    fn sample_changed(&mut self) -> bool {
+        let sample_path_id = self.params.sample_path_id.load(Ordering::Relaxed);
+        if sample_path_id == self.last_sample_path_id {
+            false
         } else {
-            self.last_rel_id = rel_id;
+            self.last_sample_path_id = sample_path_id;
+            true
         }
-        let path = self.params.rel.get_v();
+    }
 
-        if let Some(wav) = Wav::from_filepath(&Path::new(&path)) {
-            if let Some(wav) = Wavetable::slice_downsample(&wav, 2048) {
-                self.data.lock().unwrap().wavetable = wav;
-            }
+    /// Whether the instrument needs to be (re)loaded.
+    fn sfz_changed(&mut self) -> bool {
+        let sfz_path_id = self.params.sfz_path_id.load(Ordering::Relaxed);
+        if sfz_path_id == self.last_sfz_path_id {
+            false
+        } else {
+            self.last_sfz_path_id = sfz_path_id;
+            true
+        }
+    }
+
+    /// Whether the LFO shapes need to be rendered again.
+    fn lfo_shapes_changed(&mut self) -> bool {
+        let generation = self.params.lfo_shapes_generation.load(Ordering::Relaxed);
+        if generation == self.last_lfo_shapes_generation {
+            false
+        } else {
+            self.last_lfo_shapes_generation = generation;
+            true
         }
     }
 
+    fn new_global_lfos(sample_rate: f32, tables: &[Arc<LFOShapeTable>; LFOS]) -> [LFO; LFOS] {
+        // A fixed seed, so a render from the start is the same every time.
+        std::array::from_fn(|i| LFO::new(sample_rate, Voice::lfo_spec(i, tables, 0)))
+    }
+
     fn kill_voice(&mut self, i: usize) {
         self.voices.remove(i).kill();
     }
 }
 impl Default for TestPlugin {
     fn default() -> Self {
+        let params = Arc::new(TestParams::default());
+        let lfo_tables = LFOShapeTable::new_all(&params.lfo_shapes.read().unwrap());
+        let global_lfos = Self::new_global_lfos(1.0, &lfo_tables);
+        let wavetable = Arc::new(Wavetable::default());
         let data: CommonDataRef = Arc::new(Mutex::new(CommonData {
-            wavetable: Wavetable::default(),
+            wavetables: std::array::from_fn(|_| wavetable.clone()),
+            sample: Arc::new(Sample::default()),
+            instrument: None,
+            clock: 0,
+            oversampling: 1,
+            voices_started: 0,
+            retired: Default::default(),
+            transport: Transport::default(),
+            mseg: Arc::new(params.mseg.read().unwrap().clone()),
+            lfo_tables,
+            spectral_pool: Arc::new(SpectralPool::default()),
         }));
 
         Self {
-            params: Arc::new(TestParams::default()),
+            params,
             sample_rate: 1.0,
+            process_mode: ProcessMode::Realtime,
 
             voices: vec![],
+            // One for each MIDI note, so holding keys never allocates.
+            held_notes: Vec::with_capacity(128),
+            global_lfos,
             channel_tunings: [0.0; 16],
             channel_aftertouch: [0.0; 16],
 
             peak_meter: Arc::new(AtomicF32::new(nih_plug::prelude::util::MINUS_INFINITY_DB)),
 
             data,
-            last_rel_id: 0,
+            last_wavetable_path_ids: [0; WAVETABLE_SLOTS],
+            last_sample_path_id: 0,
+            last_sfz_path_id: 0,
+            last_lfo_shapes_generation: 0,
         }
     }
 }
@@ -108,16 +191,31 @@ impl Plugin for TestPlugin {
     const MIDI_INPUT: MidiConfig = MidiConfig::MidiCCs;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = Task;
 
     fn initialize(
         &mut self,
         _audio_io_layout: &AudioIOLayout,
         buffer_config: &BufferConfig,
-        _context: &mut impl InitContext<Self>,
+        context: &mut impl InitContext<Self>,
     ) -> bool {
         self.sample_rate = buffer_config.sample_rate;
-        self.update_wave();
+        self.process_mode = buffer_config.process_mode;
+        self.params.migrate_legacy_wavetable();
+        for slot in self.wavetables_changed() {
+            context.execute(Task::LoadWavetable(slot));
+        }
+        if self.sample_changed() {
+            context.execute(Task::LoadSample);
+        }
+        if self.sfz_changed() {
+            context.execute(Task::LoadSfz);
+        }
+        // Whatever the generation, as a loaded state brings its own shapes.
+        self.lfo_shapes_changed();
+        context.execute(Task::RenderLFOShapes);
+        context.execute(Task::CopyMseg);
+        context.execute(Task::FillSpectralPool);
 
         true
     }
@@ -129,18 +227,88 @@ impl Plugin for TestPlugin {
                 self.kill_voice(i);
             }
             self.voices.clear();
+            self.held_notes.clear();
         }
+        // So a render from the start gets the same voice variance every time.
+        self.data.lock().unwrap().voices_started = 0;
+        // Also called after `initialize()`, so the sample rate is known by now.
+        let lfo_tables = self.data.lock().unwrap().lfo_tables.clone();
+        self.global_lfos = Self::new_global_lfos(self.sample_rate, &lfo_tables);
     }
 
     fn params(&self) -> std::sync::Arc<dyn Params> {
         self.params.clone()
     }
 
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let params = self.params.clone();
+        let data = self.data.clone();
+        let (retired, spectral_pool) = {
+            let data = data.lock().unwrap();
+            (data.retired.clone(), data.spectral_pool.clone())
+        };
+        Box::new(move |task| match task {
+            Task::LoadSample => {
+                let path = params.sample_path.get_v();
+                if let Some(sample) = Sample::from_filepath(&Path::new(&path)) {
+                    let old = std::mem::replace(&mut data.lock().unwrap().sample, Arc::new(sample));
+                    // Voices may still be playing it, so it waits for `Task::FreeRetired`.
+                    retired.samples.retire(old);
+                }
+            }
+            Task::LoadSfz => {
+                let path = params.sfz_path.get_v();
+                if let Some((instrument, hash)) = SfzInstrument::load(&Path::new(&path)) {
+                    let saved_hash = params.sfz_hash.swap(hash, Ordering::Relaxed);
+                    if saved_hash != 0 && saved_hash != hash {
+                        nih_warn!("{path} has changed since the plugin state was saved");
+                    }
+                    let old = std::mem::replace(&mut data.lock().unwrap().instrument, Some(instrument));
+                    // Voices may still be playing its regions, so the samples wait for
+                    // `Task::FreeRetired`.
+                    if let Some(old) = old {
+                        for sample in old.into_samples() {
+                            retired.samples.retire(sample);
+                        }
+                    }
+                }
+            }
+            Task::LoadWavetable(slot) => {
+                let path = params.wavetables[slot].path.get_v();
+                if let Some(wav) = Wav::from_filepath(&Path::new(&path)) {
+                    if let Some(wavetable) = Wavetable::slice_downsample(&wav, 2048) {
+                        let mut data = data.lock().unwrap();
+                        let old = std::mem::replace(&mut data.wavetables[slot], Arc::new(wavetable));
+                        // Voices may still be playing it, so it waits for `Task::FreeRetired`. A
+                        // table still in another slot is retired when that slot is replaced.
+                        if !data.wavetables.iter().any(|table| Arc::ptr_eq(table, &old)) {
+                            retired.wavetables.retire(old);
+                        }
+                    }
+                }
+            }
+            Task::RenderLFOShapes => {
+                let tables = LFOShapeTable::new_all(&params.lfo_shapes.read().unwrap());
+                let old = std::mem::replace(&mut data.lock().unwrap().lfo_tables, tables);
+                // Voices and global LFOs may still read them, so they wait for `Task::FreeRetired`.
+                for table in old {
+                    retired.lfo_tables.retire(table);
+                }
+            }
+            Task::CopyMseg => {
+                let mseg = Arc::new(params.mseg.read().unwrap().clone());
+                let old = std::mem::replace(&mut data.lock().unwrap().mseg, mseg);
+                // Voices may still follow it, so it waits for `Task::FreeRetired`.
+                retired.msegs.retire(old);
+            }
+            Task::FreeRetired => retired.free_unused(),
+            Task::FillSpectralPool => spectral_pool.refill(),
+        })
+    }
+
     fn editor(&self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
         editor::create(
             self.params.clone(),
-            self.params.rel.clone(),
-            self.params.rel_id.clone(),
             self.peak_meter.clone(),
             self.params.editor_state.clone(),
         )
@@ -155,9 +323,38 @@ impl Plugin for TestPlugin {
         let mut midi_ev = context.next_event();
         let block_length = buffer.samples();
 
+        // Voices keep the rate they started at, only new ones pick up a change.
+        let oversampling = match self.process_mode {
+            ProcessMode::Offline => self.params.offline_oversampling.value(),
+            ProcessMode::Realtime | ProcessMode::Buffered => self.params.oversampling.value(),
+        };
+        let transport = {
+            let host = context.transport();
+            Transport {
+                tempo: host.tempo.map_or(Transport::DEFAULT_TEMPO, |tempo| tempo as f32),
+                position: if host.playing { host.pos_beats() } else { None },
+            }
+        };
+        {
+            let mut data = self.data.lock().unwrap();
+            data.oversampling = oversampling.factor();
+            data.transport = transport;
+            // Pick up any shape rendered since the last block.
+            for (lfo, table) in self.global_lfos.iter_mut().zip(&data.lfo_tables) {
+                lfo.set_table(table);
+            }
+            if !data.retired.is_empty() {
+                context.execute_background(Task::FreeRetired);
+            }
+            if data.spectral_pool.needs_refill() {
+                context.execute_background(Task::FillSpectralPool);
+            }
+        }
+
         // :::::::::::::::::::::: MIDI PROCESSING :::::::::::::::::::::: //
 
         for voice in &mut self.voices {
+            voice.freq.note.begin_block();
             voice.freq.pitchbend.begin_block();
             voice.aftertouch.begin_block();
         }
@@ -168,6 +365,7 @@ impl Plugin for TestPlugin {
                 }
                 match ev {
                     NoteEvent::Choke { note, .. } => {
+                        self.held_notes.retain(|(id, _)| id.midi_note != note);
                         if let Some(current_note) = Voice::find_by_midi_note(&mut self.voices, note)
                         {
                             current_note.choke(sample_id);
@@ -180,36 +378,66 @@ impl Plugin for TestPlugin {
                         voice_id,
                         ..
                     } => {
-                        if let Some(current_note) = Voice::find_by_midi_note(&mut self.voices, note)
-                        {
-                            current_note.release(sample_id);
+                        let voice_mode = self.params.voice_mode.value();
+                        if voice_mode == VoiceMode::Poly {
+                            if let Some(current_note) = Voice::find_by_midi_note(&mut self.voices, note)
+                            {
+                                current_note.release(sample_id);
+                            }
                         }
 
-                        // Make space if needed.
-                        Voice::sort_most_disposable_last(&mut self.voices);
-                        while self.voices.len() > MAX_POLYPHONY - 1 {
-                            self.kill_voice(MAX_POLYPHONY - 1);
+                        for lfo in &mut self.global_lfos {
+                            if lfo.scope() == LFOScope::Global {
+                                lfo.retrigger(sample_id);
+                            }
                         }
 
-                        self.voices.push(Voice::new(
-                            self.sample_rate,
-                            sample_id as u32,
-                            NoteId {
-                                midi_note: note,
-                                voice_id: voice_id.unwrap_or_default(),
-                                channel,
-                            },
-                            self.channel_tunings[channel as usize],
-                            self.channel_aftertouch[channel as usize],
-                            velocity,
-
-                            self.data.clone(),
-                        ));
+                        let id = NoteId {
+                            midi_note: note,
+                            voice_id: voice_id.unwrap_or_default(),
+                            channel,
+                        };
+                        self.held_notes.retain(|(held, _)| held.midi_note != note);
+                        self.held_notes.push((id, velocity));
+                        let mono_voice = match voice_mode {
+                            VoiceMode::Poly => None,
+                            VoiceMode::Mono | VoiceMode::Legato => Voice::find_mono(&mut self.voices),
+                        };
+                        if let Some(voice) = mono_voice {
+                            let retrigger = voice_mode == VoiceMode::Mono || !voice.is_held();
+                            voice.take_over(sample_id, id, velocity, retrigger, &self.data);
+                        } else {
+                            // Make space if needed.
+                            Voice::sort_most_disposable_last(&mut self.voices);
+                            while self.voices.len() > MAX_POLYPHONY - 1 {
+                                self.kill_voice(MAX_POLYPHONY - 1);
+                            }
+
+                            self.voices.push(Voice::new(
+                                self.sample_rate,
+                                sample_id as u32,
+                                id,
+                                self.channel_tunings[channel as usize],
+                                self.channel_aftertouch[channel as usize],
+                                velocity,
+
+                                self.data.clone(),
+                            ));
+                        }
                     }
                     NoteEvent::NoteOff { note, .. } => {
+                        self.held_notes.retain(|(held, _)| held.midi_note != note);
+                        let voice_mode = self.params.voice_mode.value();
                         if let Some(current_note) = Voice::find_by_midi_note(&mut self.voices, note)
                         {
-                            current_note.release(sample_id);
+                            // A mono voice goes back to the last key still down instead.
+                            match self.held_notes.last() {
+                                Some(&(id, velocity)) if voice_mode != VoiceMode::Poly => {
+                                    let retrigger = voice_mode == VoiceMode::Mono;
+                                    current_note.take_over(sample_id, id, velocity, retrigger, &self.data);
+                                }
+                                _ => current_note.release(sample_id),
+                            }
                         }
                     }
                     NoteEvent::MidiChannelPressure {
@@ -233,16 +461,26 @@ impl Plugin for TestPlugin {
             }
         }
         for voice in &mut self.voices {
+            voice.freq.note.finalize_block(block_length);
             voice.freq.pitchbend.finalize_block(block_length);
             voice.aftertouch.finalize_block(block_length);
         }
 
+        // :::::::::::::::::::::: GLOBAL LFOs :::::::::::::::::::::: //
+
+        for lfo in &mut self.global_lfos {
+            if lfo.scope() == LFOScope::Global {
+                lfo.block(0, block_length, &transport);
+            }
+        }
+
         // :::::::::::::::::::::: PROCESS VOICES :::::::::::::::::::::: //
         let mut out = [vec![0.0; block_length], vec![0.0; block_length]];
 
         for voice in &mut self.voices {
-            voice.process(&mut out);
+            voice.process(&mut out, &self.global_lfos, &transport);
         }
+        self.data.lock().unwrap().clock += block_length as u64;
 
         for (sample_id, samples) in buffer.iter_samples().enumerate() {
             for (i, sample) in samples.into_iter().enumerate() {
@@ -267,7 +505,18 @@ impl Plugin for TestPlugin {
         // To save resources, a plugin can (and probably should!) only perform expensive
         // calculations that are only displayed on the GUI while the GUI is open
         if self.params.editor_state.is_open() {
-            self.update_wave();
+            for slot in self.wavetables_changed() {
+                context.execute_background(Task::LoadWavetable(slot));
+            }
+            if self.sample_changed() {
+                context.execute_background(Task::LoadSample);
+            }
+            if self.sfz_changed() {
+                context.execute_background(Task::LoadSfz);
+            }
+            if self.lfo_shapes_changed() {
+                context.execute_background(Task::RenderLFOShapes);
+            }
             for sample_id in 0 .. block_length {
                 let wave:[f32; 2] = std::array::from_fn(|i| out[i][sample_id]);
 
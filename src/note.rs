@@ -1,22 +1,38 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, sync::Arc};
 
 use nih_plug::prelude::SmoothingStyle;
 
 use crate::{
     component::{
-        env_adsr::{ADSRSpec, EnvelopeADSR},
+        env_adsr::{ADSRSpec, EnvStage, EnvMode, EnvelopeADSR},
         params::{InputFrequencyParam, InputParam, ParamSourceImpl, ParamPolarity},
-        lfo::{LFOPhase, LFOSpec, LFO},
-        noiseosc::{NoiseOscillator, NoiseOscillatorSpec, NoiseType, MultichunkWhiteNoiseGen},
-        oscillator::{Oscillator, OscillatorSpec, UnisonSpec, UnisonFalloff, UnisonPhase},
-        subosc::{SubOscillator, SubOscillatorSpec},
+        lfo::{LFOSpec, LFO, LFORate, LFOScope, LFOWaveform, LFOS},
+        lfo_shape::LFOShapeTable,
+        lfo_random::{LFORandom, LFORandomKind},
+        noiseosc::{NoiseOscillator, NoiseOscillatorSpec, NoiseType, NoiseSeed},
+        oscillator::{Oscillator, OscillatorSpec, WavetableSpec, UnisonSpec, UnisonFalloff},
+        subosc::{SubOscillator, SubOscillatorSpec, SubMode, SubLockSource},
+        crossmod::{CrossModSpec, CrossModMode, CrossModSource},
+        sync::{SyncSpec, SyncSource},
+        phase_warp::PhaseWarpSpec,
+        sampler::{Sampler, SamplerSpec, SamplerLoopSpec},
+        sfz::SfzRegion,
+        additive::{AdditiveOscillator, AdditiveOscillatorSpec, AdditiveMacroSpec, PartialSpec},
+        pluck::{PluckedString, PluckedStringSpec, PluckExcitationSpec},
+        granular::{GranularOscillator, GranularSpec, GrainSpec, GrainWindow},
+        phase_mode::PhaseMode,
+        tuning::TuningSpec,
+        fm::{FmEngine, FmSpec, FmAlgorithm, FmOperatorSpec, FmFrequency, FmWaveform},
+        analog::{Analog, AnalogSpec},
+        mseg::{Mseg, MsegSpec},
     },
-    util::simple_waveforms::SimpleWaveform, common_data::CommonDataRef,
+    util::{simple_waveforms::SimpleWaveform, decimator::Decimator, fnv::Fnv1a64, note_division::{NoteDivision, NoteModifier}}, common_data::{CommonDataRef, Transport},
 };
 
-use self::{id::NoteId, state::NoteState};
+use self::{id::NoteId, state::NoteState, mix::VoiceMix};
 
 pub mod id;
+pub mod mix;
 pub mod state;
 
 pub struct Voice {
@@ -26,13 +42,29 @@ pub struct Voice {
     pub freq: InputFrequencyParam,
     pub velocity: f32,
     pub aftertouch: InputParam,
+    /// picked for a note taken over, and loaded once the voice starts over with it
+    retrigger_region: Option<SfzRegion>,
 
     pub envs: [EnvelopeADSR; 2],
-    pub lfos: [LFO; 4],
+    pub mseg: Mseg,
+    pub lfos: [LFO; LFOS],
     pub subosc: SubOscillator,
     pub noiseosc: NoiseOscillator,
     pub osc_p: Oscillator,
     pub oscs: [Oscillator; 2],
+    pub sampler: Sampler,
+    pub granular: GranularOscillator,
+    pub additive: AdditiveOscillator,
+    pub pluck: PluckedString,
+    pub fm: FmEngine,
+
+    pub analog: Analog,
+    pub mix: VoiceMix,
+
+    /// how many times the host rate this voice runs at
+    oversampling: usize,
+    decimators: [Decimator; 2],
+    oversampled: [Vec<f32>; 2],
 }
 
 impl Voice {
@@ -46,75 +78,256 @@ impl Voice {
 
         data: CommonDataRef,
     ) -> Self {
+        let (region, note_time, oversampling, seed, mseg, lfo_tables) = {
+            let mut data = data.lock().unwrap();
+            let region = data.instrument.as_mut()
+                .and_then(|instrument| instrument.pick_region(&id, velocity));
+            let note_time = (data.clock + trigger_in as u64) as f64 / sample_rate as f64;
+            let mut seed = Fnv1a64::new();
+            seed.write(&data.voices_started.to_le_bytes());
+            seed.write(&[id.midi_note]);
+            data.voices_started += 1;
+            let mseg = MsegSpec::new(data.mseg.clone(), data.transport.tempo);
+            (region, note_time, data.oversampling.max(1), seed.finish(), mseg, data.lfo_tables.clone())
+        };
+        // Everything below runs at the oversampled rate.
+        let sample_rate = sample_rate * oversampling as f32;
+
         let mut self_ = Self {
             envs: [
-                EnvelopeADSR::new(ADSRSpec::linear(0.005, 0.0, 1.0, 0.005)),
-                EnvelopeADSR::new(ADSRSpec::linear(0.0, 0.0, 0.0, 0.0)),
+                EnvelopeADSR::new(ADSRSpec::new(
+                    0.0,
+                    EnvStage::linear(0.005),
+                    0.0,
+                    EnvStage::linear(0.0),
+                    1.0,
+                    EnvStage::linear(0.005),
+                    EnvMode::Normal,
+                )),
+                EnvelopeADSR::new(ADSRSpec::new(
+                    0.0,
+                    EnvStage::new(0.0, 4.0),
+                    0.0,
+                    EnvStage::new(0.0, 4.0),
+                    0.0,
+                    EnvStage::new(0.0, 4.0),
+                    EnvMode::Normal,
+                )),
             ],
-            lfos: std::array::from_fn(|_| {
-                LFO::new(
-                    sample_rate,
-                    LFOSpec::new(
-                        2.0,
-                        LFOPhase::AT(0.0),
-                        SimpleWaveform::SINE,
-                    ),
-                )
-            }),
+            lfos: std::array::from_fn(|i| LFO::new(sample_rate, Self::lfo_spec(i, &lfo_tables, seed))),
             osc_p: Oscillator::new(sample_rate, OscillatorSpec::new(
                 UnisonSpec::new(
                     10,
                     UnisonFalloff::Linear,
                     5.0,
-                    UnisonPhase::Random,
+                    PhaseMode::Random { phase: 0.0, amount: 1.0 },
                 ),
                 data.clone(),
-                0.0,
-                0.5,
-            )),
+                TuningSpec::keytracked(0.0),
+                WavetableSpec::single(0, 0.5),
+                CrossModSpec::none(),
+                SyncSpec::none(),
+                PhaseWarpSpec::none(),
+            ), seed.wrapping_add(6)),
             oscs: [
                 Oscillator::new(sample_rate, OscillatorSpec::new(
                     UnisonSpec::new(
                         4,
                         UnisonFalloff::Linear,
                         2.0,
-                        UnisonPhase::Random,
+                        PhaseMode::Random { phase: 0.0, amount: 1.0 },
                     ),
                     data.clone(),
-                    0.0,
-                    0.5,
-                )),
+                    TuningSpec::keytracked(0.0),
+                    WavetableSpec::single(0, 0.5),
+                    CrossModSpec::new(CrossModMode::FM, CrossModSource::OscP, 0.0),
+                    SyncSpec::none(),
+                    PhaseWarpSpec::none(),
+                ), seed.wrapping_add(7)),
                 Oscillator::new(sample_rate, OscillatorSpec::new(
                     UnisonSpec::new(
                         4,
                         UnisonFalloff::Linear,
                         5.0,
-                        UnisonPhase::Random,
+                        PhaseMode::Random { phase: 0.0, amount: 1.0 },
                     ),
                     data.clone(),
-                    0.0,
-                    0.5,
-                )),
+                    TuningSpec::keytracked(0.0),
+                    WavetableSpec::single(0, 0.5),
+                    CrossModSpec::none(),
+                    SyncSpec::none(),
+                    PhaseWarpSpec::none(),
+                ), seed.wrapping_add(8)),
             ],
             subosc: SubOscillator::new(sample_rate, SubOscillatorSpec::new(
-                0.0,
+                TuningSpec::keytracked(0.0),
                 SimpleWaveform::SAW,
+                PhaseMode::Retrigger(0.0),
+                SubMode::Free,
+                0.5,
+            )),
+            noiseosc: NoiseOscillator::new(sample_rate, NoiseOscillatorSpec::new(
+                NoiseType::MultichunkWhiteNoise,
+                NoiseSeed::Random,
+                false,
+                0.0,
+            )),
+
+            sampler: Sampler::new(sample_rate, SamplerSpec::new(
+                data.clone(),
+                60,
+                false,
+                TuningSpec::keytracked(0.0),
+                0.0,
+                SamplerLoopSpec::one_shot(),
+            )),
+
+            granular: GranularOscillator::new(sample_rate, GranularSpec::new(
+                data.clone(),
+                60,
+                TuningSpec::keytracked(0.0),
+                0.0,
+                GrainSpec::new(
+                    0.08,
+                    40.0,
+                    0.02,
+                    0.0,
+                    GrainWindow::Hann,
+                ),
+                0.5,
+            )),
+
+            additive: AdditiveOscillator::new(sample_rate, AdditiveOscillatorSpec::new(
+                UnisonSpec::new(
+                    1,
+                    UnisonFalloff::Linear,
+                    0.0,
+                    PhaseMode::Retrigger(0.0),
+                ),
+                PartialSpec::harmonic_series(32, |k| 0.5 / k as f32),
+                TuningSpec::keytracked(0.0),
+                AdditiveMacroSpec::neutral(),
             )),
-            noiseosc: NoiseOscillator::new(NoiseOscillatorSpec::new(
-                NoiseType::MultichunkWhiteNoise(MultichunkWhiteNoiseGen::new()),
+
+            // Seeded apart from the analog variance below, so the two don't correlate.
+            pluck: PluckedString::new(sample_rate, PluckedStringSpec::new(
+                TuningSpec::keytracked(0.0),
+                4.0,
+                0.5,
+                0.2,
+                0.0,
+                PluckExcitationSpec::noise(),
+            ), seed.wrapping_add(1)),
+
+            mseg: Mseg::new(sample_rate, mseg),
+
+            fm: FmEngine::new(sample_rate, FmSpec::new(
+                data.clone(),
+                TuningSpec::keytracked(0.0),
+                FmAlgorithm::Pairs,
+                [
+                    FmOperatorSpec::new(FmFrequency::Ratio(1.0), FmWaveform::Sine, 1.0, 0.0, 0.3, ADSRSpec::linear(0.001, 3.0, 0.0, 0.3)),
+                    FmOperatorSpec::new(FmFrequency::Ratio(14.0), FmWaveform::Sine, 0.15, 0.0, 0.8, ADSRSpec::linear(0.001, 0.4, 0.0, 0.3)),
+                    FmOperatorSpec::new(FmFrequency::Ratio(1.0), FmWaveform::Sine, 1.0, 0.0, 0.3, ADSRSpec::linear(0.001, 2.0, 0.2, 0.3)),
+                    FmOperatorSpec::new(FmFrequency::Ratio(1.0), FmWaveform::Sine, 0.2, 0.3, 0.6, ADSRSpec::linear(0.001, 1.5, 0.1, 0.3)),
+                ],
             )),
+
+            analog: Analog::new(sample_rate, AnalogSpec::new(
+                0.5,
+                3.0,
+                4.0,
+                2.0,
+                0.3,
+                0.03,
+                0.03,
+            ), seed),
+            mix: VoiceMix::new([0.6, 0.0], 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0),
             
-            freq: InputFrequencyParam::new(sample_rate, id.midi_note, pitchbend),
+            freq: InputFrequencyParam::new(sample_rate, id.midi_note, pitchbend, oversampling),
             velocity,
-            aftertouch: InputParam::new(sample_rate, aftertouch, SmoothingStyle::Linear(2.0)),
+            aftertouch: InputParam::new(sample_rate, aftertouch, SmoothingStyle::Linear(2.0), oversampling),
+            retrigger_region: None,
             
-            state: NoteState::new(sample_rate, trigger_in),
+            state: NoteState::new(sample_rate, trigger_in * oversampling as u32),
             id,
+
+            oversampling,
+            decimators: std::array::from_fn(|_| Decimator::new(oversampling)),
+            oversampled: [vec![], vec![]],
         };
+        if let Some(region) = &region {
+            self_.sampler.load_sfz_region(region);
+            // An instrument was loaded to be heard.
+            self_.mix.sampler.rebase(1.0);
+        }
+        self_.set_note_time(note_time);
+        self_.fm.set_velocity(velocity);
+        self_.apply_analog();
         self_.reset();
         return self_;
     }
 
+    /// Spec of LFO `i`, shared by the voices and the global LFO they follow in its place.
+    /// Random waveforms are seeded from `seed`, apart from the other seeded components.
+    pub fn lfo_spec(i: usize, tables: &[Arc<LFOShapeTable>; LFOS], seed: u64) -> LFOSpec {
+        let random_seed = NoiseSeed::Fixed(seed.wrapping_add(2 + i as u64));
+        let (rate, waveform, scope) = match i {
+            1 => (LFORate::Hz(2.0), LFOWaveform::Random(LFORandom::new(LFORandomKind::Smooth, random_seed)), LFOScope::Voice),
+            2 => (LFORate::Hz(2.0), LFOWaveform::Custom(tables[i].clone()), LFOScope::Voice),
+            3 => (
+                LFORate::Synced { division: NoteDivision::new(1.0 / 4.0, NoteModifier::Straight), locked: true },
+                LFOWaveform::Simple(SimpleWaveform::SINE),
+                LFOScope::Global,
+            ),
+            _ => (LFORate::Hz(2.0), LFOWaveform::Simple(SimpleWaveform::SINE), LFOScope::Voice),
+        };
+        LFOSpec::new(
+            rate,
+            PhaseMode::Retrigger(0.0),
+            waveform,
+            scope,
+            0.0,
+            0.0,
+            false,
+        )
+    }
+    /// Tell components where the note starts on the global clock, for free-running phase.
+    fn set_note_time(&mut self, note_time: f64) {
+        for lfo in &mut self.lfos {
+            lfo.set_note_time(note_time);
+        }
+        self.subosc.set_note_time(note_time);
+        self.osc_p.set_note_time(note_time);
+        for osc in &mut self.oscs {
+            osc.set_note_time(note_time);
+        }
+        self.additive.set_note_time(note_time);
+    }
+    /// Detune this voice and vary its envelopes, by its share of analog variance.
+    fn apply_analog(&mut self) {
+        let analog = &mut self.analog;
+        let [osc_0, osc_1] = &mut self.oscs;
+        for tuning in [
+            &mut self.osc_p.tuning,
+            &mut osc_0.tuning,
+            &mut osc_1.tuning,
+            &mut self.subosc.tuning,
+            &mut self.sampler.tuning,
+            &mut self.granular.tuning,
+            &mut self.additive.tuning,
+            &mut self.pluck.tuning,
+            &mut self.fm.tuning,
+        ] {
+            analog.apply_tuning(tuning);
+        }
+        for env in &mut self.envs {
+            analog.vary_envelope(env);
+        }
+        for operator in &mut self.fm.operators {
+            analog.vary_envelope(&mut operator.envelope);
+        }
+    }
     fn reset(&mut self) {
         // reset smoothers here
     }
@@ -126,19 +339,73 @@ impl Voice {
     }
 
     pub fn release(&mut self, in_samples: usize) {
-        self.state.mark_released_in(in_samples as u32);
+        // A one-shot sample plays out, and ends the voice itself.
+        if self.sampler.ignores_release() {
+            return;
+        }
+        self.state.mark_released_in((in_samples * self.oversampling) as u32);
+    }
+    /// Play the voice again from the start of its envelopes, for mono and legato playing.
+    pub fn retrigger(&mut self, in_samples: usize) {
+        self.state.mark_retrigger_in((in_samples * self.oversampling) as u32);
+    }
+    /// Move a mono voice on to the note `id`, `in_samples` into the block, starting it over
+    /// if `retrigger`. Call between `begin_block` and `finalize_block` of `freq.note`.
+    ///
+    /// Velocity and the SFZ region are picked as a note starts, so a legato note keeps those
+    /// of the note it follows.
+    pub fn take_over(&mut self, in_samples: usize, id: NoteId, velocity: f32, retrigger: bool, data: &CommonDataRef) {
+        self.freq.note.update_block(in_samples, id.midi_note as f32);
+        if retrigger {
+            self.retrigger_region = data.lock().unwrap().instrument.as_mut()
+                .and_then(|instrument| instrument.pick_region(&id, velocity));
+            self.velocity = velocity;
+            self.retrigger(in_samples);
+        }
+        self.id = id;
+    }
+    /// Whether the note is held, counting a release due later in the block as let go.
+    pub fn is_held(&self) -> bool {
+        self.state.stays_held()
     }
     pub fn choke(&mut self, in_samples: usize) {
-        self.state.mark_choke_in(in_samples as u32);
+        self.state.mark_choke_in((in_samples * self.oversampling) as u32);
     }
 
-    pub fn process(&mut self, out: &mut [Vec<f32>; 2]) {
+    /// Render the voice and add it to `out`, at the host rate. `global_lfos` have run for
+    /// this block already.
+    pub fn process(&mut self, out: &mut [Vec<f32>; 2], global_lfos: &[LFO; LFOS], transport: &Transport) {
+        if self.oversampling == 1 {
+            self.render(out, global_lfos, transport);
+            return;
+        }
+        let mut oversampled = std::mem::take(&mut self.oversampled);
+        for (channel, out) in oversampled.iter_mut().zip(out.iter()) {
+            channel.clear();
+            channel.resize(out.len() * self.oversampling, 0.0);
+        }
+        self.render(&mut oversampled, global_lfos, transport);
+        for ((channel, decimator), out) in oversampled.iter_mut().zip(&mut self.decimators).zip(out.iter_mut()) {
+            decimator.process(channel);
+            for (out, value) in out.iter_mut().zip(channel.iter()) {
+                *out += value;
+            }
+        }
+        self.oversampled = oversampled;
+    }
+    fn render(&mut self, out: &mut [Vec<f32>; 2], global_lfos: &[LFO; LFOS], transport: &Transport) {
         let block_len = out[0].len();
         let trigger_at = self.state.get_trigger_at();
 
         // :::::::::::::::::::::: PREP :::::::::::::::::::::: //
 
         self.freq.prepare();
+        // Nothing cross-modulates from, syncs to or is excited by these, so one turned all the
+        // way down isn't heard and is left out.
+        let granular_heard = self.mix.granular.base() > 0.0;
+        let additive_heard = self.mix.additive.base() > 0.0;
+        let fm_heard = self.mix.fm.base() > 0.0;
+        let pluck_heard = self.mix.pluck.base() > 0.0;
 
         
         
@@ -150,14 +417,32 @@ impl Voice {
         for env in &mut self.envs {
             env.begin_block();
         }
-        for _ in 0 .. block_len {
+        self.mseg.begin_block();
+        self.fm.begin_block();
+        let mut retrigger_at = None;
+        for i in 0 .. block_len {
             let current_state = self.state.current_raw();
+            if current_state.retriggered && retrigger_at.is_none() {
+                retrigger_at = Some(i);
+            }
             for env in &mut self.envs {
                 env.update_block(&current_state);
             }
+            self.mseg.update_block(&current_state);
+            self.fm.update_block(&current_state);
             self.state.tick();
         }
         self.envs[0].update_note_ended(&mut self.state);
+        // The envelopes start over by themselves, the rest is told where.
+        if let Some(at) = retrigger_at {
+            if let Some(region) = self.retrigger_region.take() {
+                self.sampler.load_sfz_region(&region);
+            }
+            self.sampler.retrigger(at);
+            self.pluck.retrigger(at);
+            self.fm.set_velocity(self.velocity);
+            self.fm.retrigger(at);
+        }
 
         // :::::::::::::::::::::: LINK [LFOs] :::::::::::::::::::::: //
 
@@ -165,22 +450,89 @@ impl Voice {
 
         // :::::::::::::::::::::: LFOs :::::::::::::::::::::: //
 
-        for lfo in &mut self.lfos {
-            lfo.block(trigger_at, block_len);
+        for (lfo, global) in self.lfos.iter_mut().zip(global_lfos) {
+            match lfo.scope() {
+                LFOScope::Voice => lfo.block(trigger_at, block_len, transport),
+                LFOScope::Global => lfo.follow(global, self.oversampling),
+            }
+        }
+
+        // :::::::::::::::::::::: ANALOG :::::::::::::::::::::: //
+
+        self.analog.block(block_len);
+
+        // :::::::::::::::::::::: LINK [SUB, NOISE & SAMPLERs] :::::::::::::::::::::: //
+
+        self.subosc.freq.send_key_track_tuned(&self.freq, &self.subosc.tuning);
+        self.noiseosc.freq.send_key_track(&self.freq);
+        self.sampler.freq.send_key_track_tuned(&self.freq, &self.sampler.tuning);
+        self.analog.send_pitch(&mut self.subosc.freq, &SubOscillator::rangeof_freq(), None);
+        self.analog.send_pitch(&mut self.sampler.freq, &Sampler::rangeof_freq(), None);
+        if granular_heard {
+            self.granular.freq.send_key_track_tuned(&self.freq, &self.granular.tuning);
+            self.analog.send_pitch(&mut self.granular.freq, &GranularOscillator::rangeof_freq(), None);
+        }
+
+        // :::::::::::::::::::::: SUB, NOISE & SAMPLERs :::::::::::::::::::::: //
+
+        // These run first so they can cross-modulate the wavetable oscillators. A locked sub
+        // waits for the oscillator it follows instead.
+        let mut sub_rendered = self.subosc.lock_source().is_none();
+        if sub_rendered {
+            self.subosc.block(trigger_at, block_len);
+        }
+        self.noiseosc.block(trigger_at, block_len);
+        if !self.state.held {
+            self.sampler.release();
+        }
+        self.sampler.block(trigger_at, block_len);
+        if self.sampler.ignores_release() {
+            self.sampler.update_note_ended(&mut self.state);
+        }
+        if granular_heard {
+            self.granular.block(trigger_at, block_len);
+        } else {
+            self.granular.skip(block_len);
         }
 
         // :::::::::::::::::::::: LINK [MOD OSCILLATOR] :::::::::::::::::::::: //
 
+        self.osc_p.freq.send_key_track_tuned(&self.freq, &self.osc_p.tuning);
+        self.analog.send_pitch(&mut self.osc_p.freq, &Oscillator::rangeof_freq(), Some(0));
+        match self.osc_p.crossmod.source {
+            CrossModSource::Sub => if sub_rendered {
+                self.osc_p.crossmod.send(&self.subosc);
+            },
+            CrossModSource::Noise => self.osc_p.crossmod.send(&self.noiseosc),
+            CrossModSource::Sampler => self.osc_p.crossmod.send(&self.sampler),
+            CrossModSource::OscP | CrossModSource::Osc(_) => (),
+        }
+        match self.osc_p.sync.source {
+            SyncSource::Sub => if sub_rendered {
+                self.osc_p.sync.send(&self.subosc);
+            },
+            SyncSource::OscP | SyncSource::Osc(_) => (),
+        }
+
         // :::::::::::::::::::::: MOD OSCILLATOR :::::::::::::::::::::: //
 
         self.osc_p.block(trigger_at, block_len);
+        if let Some(SubLockSource::OscP) = self.subosc.lock_source() {
+            self.subosc.send_lock(&self.osc_p);
+            self.subosc.block(trigger_at, block_len);
+            sub_rendered = true;
+        }
 
         // :::::::::::::::::::::: LINK [MAIN OSCILLATORs] :::::::::::::::::::::: //
 
-        for osc in &mut self.oscs {
-            osc.freq.send_key_track(&self.freq);
+        for (i, osc) in self.oscs.iter_mut().enumerate() {
+            osc.freq.send_key_track_tuned(&self.freq, &osc.tuning);
+            self.analog.send_pitch(&mut osc.freq, &Oscillator::rangeof_freq(), Some(i + 1));
+        }
+        if additive_heard {
+            self.additive.freq.send_key_track_tuned(&self.freq, &self.additive.tuning);
+            self.analog.send_pitch(&mut self.additive.freq, &AdditiveOscillator::rangeof_freq(), None);
         }
-        self.subosc.freq.send_key_track(&self.freq);
 
         // self.oscs[0].freq.send(&self.lfos[0], ParamPolarity::Bipolar, 0.0005);
         self.oscs[0].slice.send(&self.aftertouch, ParamPolarity::Bipolar, 0.5);
@@ -188,12 +540,78 @@ impl Voice {
 
         // :::::::::::::::::::::: MAIN OSCILLATORs :::::::::::::::::::::: //
 
-        for osc in &mut self.oscs {
-            osc.block(trigger_at, block_len);
+        // Render the modulating oscillator first if one main oscillator modulates the other.
+        let order = if self.oscs[0].depends_on_osc(1) {
+            [1, 0]
+        } else {
+            [0, 1]
+        };
+        let mut rendered = [false; 2];
+        for i in order {
+            self.link_oscillator(i, &rendered, sub_rendered);
+            self.oscs[i].block(trigger_at, block_len);
+            rendered[i] = true;
+            if let Some(SubLockSource::Osc(j)) = self.subosc.lock_source() {
+                if j == i {
+                    self.subosc.send_lock(&self.oscs[i]);
+                    self.subosc.block(trigger_at, block_len);
+                    sub_rendered = true;
+                }
+            }
+        }
+        if !sub_rendered {
+            // Locked to an oscillator that doesn't exist, render it silent to keep the block.
+            self.subosc.block(trigger_at, block_len);
+        }
+        if additive_heard {
+            self.additive.block(trigger_at, block_len);
+        } else {
+            self.additive.skip(block_len);
         }
 
-        self.subosc.block(trigger_at, block_len);
-        self.noiseosc.block(trigger_at, block_len);
+        // :::::::::::::::::::::: LINK [FM] :::::::::::::::::::::: //
+
+        if fm_heard {
+            self.fm.freq.send_key_track_tuned(&self.freq, &self.fm.tuning);
+            self.analog.send_pitch(&mut self.fm.freq, &FmEngine::rangeof_freq(), None);
+        }
+
+        // :::::::::::::::::::::: FM :::::::::::::::::::::: //
+
+        if fm_heard {
+            self.fm.block(trigger_at, block_len);
+        } else {
+            self.fm.skip(block_len);
+        }
+
+        // :::::::::::::::::::::: LINK [PLUCK] :::::::::::::::::::::: //
+
+        if pluck_heard {
+            self.pluck.freq.send_key_track_tuned(&self.freq, &self.pluck.tuning);
+            self.analog.send_pitch(&mut self.pluck.freq, &PluckedString::rangeof_freq(), None);
+            match self.pluck.excitation_source {
+                Some(CrossModSource::OscP) => self.pluck.send_excitation(&self.osc_p),
+                Some(CrossModSource::Osc(i)) => if let Some(osc) = self.oscs.get(i) {
+                    self.pluck.send_excitation(osc);
+                },
+                Some(CrossModSource::Sub) => self.pluck.send_excitation(&self.subosc),
+                Some(CrossModSource::Noise) => self.pluck.send_excitation(&self.noiseosc),
+                Some(CrossModSource::Sampler) => self.pluck.send_excitation(&self.sampler),
+                None => (),
+            }
+        }
+
+        // :::::::::::::::::::::: PLUCK :::::::::::::::::::::: //
+
+        if pluck_heard {
+            self.pluck.block(trigger_at, block_len);
+        } else {
+            self.pluck.skip(block_len);
+        }
+        if self.mix.is_pluck_only() {
+            // Let the string ring out instead of holding the voice open.
+            self.pluck.update_note_ended(&mut self.state);
+        }
 
         // :::::::::::::::::::::: LINK [EFFECTs] :::::::::::::::::::::: //
 
@@ -203,13 +621,85 @@ impl Voice {
 
         // >>>>>>>>>> TEMP OUTPUT
         let env_0_out = self.envs[0].get_param_buffer(ParamPolarity::Monopolar);
-        let osc_0_out = self.oscs[0].get_param_buffer(ParamPolarity::Bipolar);
+        let osc_out = [
+            self.oscs[0].get_param_buffer(ParamPolarity::Bipolar),
+            self.oscs[1].get_param_buffer(ParamPolarity::Bipolar),
+        ];
         let sub_out = self.subosc.get_param_buffer(ParamPolarity::Bipolar);
+        let noise_out = self.noiseosc.stereo_buffers();
+        let sampler_out = self.sampler.stereo_buffers();
+        let granular_out = self.granular.stereo_buffers();
+        let additive_out = self.additive.get_param_buffer(ParamPolarity::Bipolar);
+        let pluck_out = self.pluck.get_param_buffer(ParamPolarity::Bipolar);
+        let fm_out = self.fm.get_param_buffer(ParamPolarity::Bipolar);
+
+        let [osc_0_level, osc_1_level] = &mut self.mix.oscs;
+        let osc_level = [osc_0_level.take(block_len), osc_1_level.take(block_len)];
+        let sub_level = self.mix.sub.take(block_len);
+        let noise_level = self.mix.noise.take(block_len);
+        let sampler_level = self.mix.sampler.take(block_len);
+        let granular_level = self.mix.granular.take(block_len);
+        let additive_level = self.mix.additive.take(block_len);
+        let pluck_level = self.mix.pluck.take(block_len);
+        let fm_level = self.mix.fm.take(block_len);
         for i in 0 .. block_len {
             let gain = env_0_out[i];
-            let osc = osc_0_out[i] * 0.6;// + sub_out[i] * 0.2;
-            out[0][i] += osc * gain;
-            out[1][i] += osc * gain;
+            let mono = osc_out[0][i] * osc_level[0][i]
+                + osc_out[1][i] * osc_level[1][i]
+                + sub_out[i] * sub_level[i]
+                + additive_out[i] * additive_level[i]
+                + pluck_out[i] * pluck_level[i]
+                + fm_out[i] * fm_level[i];
+            for (channel, out) in out.iter_mut().enumerate() {
+                let stereo = noise_out[channel][i] * noise_level[i]
+                    + sampler_out[channel][i] * sampler_level[i]
+                    + granular_out[channel][i] * granular_level[i];
+                out[i] += (mono + stereo) * gain;
+            }
+        }
+    }
+
+    /// Send already rendered components to the cross-modulation and sync inputs of `oscs[i]`.
+    ///
+    /// `osc_p` renders before every other wavetable oscillator, so it can't follow any of them,
+    /// and a main oscillator only follows the ones rendered before it. A source that isn't
+    /// rendered yet, or an index past the last oscillator, is ignored.
+    fn link_oscillator(&mut self, i: usize, rendered: &[bool; 2], sub_rendered: bool) {
+        let (before, rest) = self.oscs.split_at_mut(i);
+        let (osc, after) = rest.split_first_mut().unwrap();
+        let (before, after): (&[Oscillator], &[Oscillator]) = (before, after);
+        // Sources past the last oscillator are ignored, like ones not rendered yet.
+        let other = move |j: usize| {
+            if !rendered.get(j).copied().unwrap_or(false) {
+                None
+            } else if j < i {
+                before.get(j)
+            } else if j > i {
+                after.get(j - i - 1)
+            } else {
+                None
+            }
+        };
+
+        match osc.crossmod.source {
+            CrossModSource::OscP => osc.crossmod.send(&self.osc_p),
+            CrossModSource::Sub => if sub_rendered {
+                osc.crossmod.send(&self.subosc);
+            },
+            CrossModSource::Noise => osc.crossmod.send(&self.noiseosc),
+            CrossModSource::Sampler => osc.crossmod.send(&self.sampler),
+            CrossModSource::Osc(j) => if let Some(other) = other(j) {
+                osc.crossmod.send(other);
+            },
+        }
+        match osc.sync.source {
+            SyncSource::OscP => osc.sync.send(&self.osc_p),
+            SyncSource::Sub => if sub_rendered {
+                osc.sync.send(&self.subosc);
+            },
+            SyncSource::Osc(j) => if let Some(other) = other(j) {
+                osc.sync.send(other);
+            },
         }
     }
 
@@ -232,6 +722,12 @@ impl Voice {
                 .cmp(&b.state.samples_since_changed())
         }
     }
+    /// The voice a mono voice mode keeps playing, the one least disposable.
+    pub fn find_mono(voices: &mut [Voice]) -> Option<&mut Voice> {
+        voices.iter_mut()
+            .filter(|voice| !voice.is_ended())
+            .min_by(|a, b| Self::ord_most_disposible(a, b))
+    }
     pub fn find_by_midi_note(voices: &mut Vec<Voice>, midi_note_id: u8) -> Option<&mut Voice> {
         for voice in voices {
             if voice.state.held && voice.id.midi_note == midi_note_id {
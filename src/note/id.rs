@@ -1,5 +1,6 @@
 
 
+#[derive(Clone, Copy)]
 pub struct NoteId {
     pub midi_note: u8,
     pub voice_id: i32,
@@ -0,0 +1,43 @@
+use crate::{component::params::Param, util::param_range::ParamRange};
+
+/// Levels of each sound source in the voice's output.
+pub struct VoiceMix {
+    pub oscs: [Param; 2],
+    pub sub: Param,
+    pub noise: Param,
+    pub sampler: Param,
+    pub additive: Param,
+    pub pluck: Param,
+    pub granular: Param,
+    pub fm: Param,
+}
+impl VoiceMix {
+    pub fn rangeof_level() -> ParamRange { ParamRange::linear(0.0, 1.0) }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        oscs: [f32; 2],
+        sub: f32,
+        noise: f32,
+        sampler: f32,
+        additive: f32,
+        pluck: f32,
+        granular: f32,
+        fm: f32,
+    ) -> Self {
+        Self {
+            oscs: oscs.map(|level| Param::new(level, Self::rangeof_level())),
+            sub: Param::new(sub, Self::rangeof_level()),
+            noise: Param::new(noise, Self::rangeof_level()),
+            sampler: Param::new(sampler, Self::rangeof_level()),
+            additive: Param::new(additive, Self::rangeof_level()),
+            pluck: Param::new(pluck, Self::rangeof_level()),
+            granular: Param::new(granular, Self::rangeof_level()),
+            fm: Param::new(fm, Self::rangeof_level()),
+        }
+    }
+    /// Whether the plucked string is the only source turned up, so the voice can end with it.
+    pub fn is_pluck_only(&self) -> bool {
+        let others = [&self.oscs[0], &self.oscs[1], &self.sub, &self.noise, &self.sampler, &self.additive, &self.granular, &self.fm];
+        self.pluck.base() > 0.0 && others.iter().all(|level| level.base() == 0.0)
+    }
+}
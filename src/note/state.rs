@@ -7,6 +7,10 @@ pub struct NoteState {
     release_in: u32,
     choking: bool,
     choke_in: u32,
+    retriggering: bool,
+    retrigger_in: u32,
+    /// whether the last tick started the note over
+    retriggered: bool,
     pub samples_since_trigger: u32,
     pub samples_since_release: u32,
 
@@ -25,6 +29,9 @@ impl NoteState {
             release_in: 0,
             choking: false,
             choke_in: 0,
+            retriggering: false,
+            retrigger_in: 0,
+            retriggered: false,
             ended: false,
         }
     }
@@ -39,10 +46,16 @@ impl NoteState {
         self.choking = true;
         self.choke_in = release_in;
     }
+    /// Start the note over without ending it, as a mono or legato voice is played again.
+    pub fn mark_retrigger_in(&mut self, retrigger_in: u32) {
+        self.retriggering = true;
+        self.retrigger_in = retrigger_in;
+    }
     pub fn mark_ended(&mut self) {
         self.ended = true;
     }
     pub fn tick(&mut self) {
+        self.retriggered = false;
         if self.choking {
             if self.choke_in > 0 { // this is inside so we don't kill instantly.
                 self.choke_in -= 1;
@@ -61,12 +74,32 @@ impl NoteState {
             self.trigger_in -= 1;
             return;
         }
+        if self.retriggering {
+            if self.retrigger_in > 0 {
+                self.retrigger_in -= 1;
+            } else {
+                self.retriggering = false;
+                self.retriggered = true;
+                self.held = true;
+                // A release that already happened belongs to the last time the note was played.
+                if self.release_in == 0 {
+                    self.releasing = false;
+                }
+                self.samples_since_trigger = 0;
+                self.samples_since_release = 0;
+                return;
+            }
+        }
 
         self.samples_since_trigger += 1;
         if !self.held {
             self.samples_since_release += 1;
         }
     }
+    /// Whether the note is held and no release is waiting to happen.
+    pub fn stays_held(&self) -> bool {
+        self.held && !self.releasing
+    }
     pub fn has_triggered(&self) -> bool {
         self.trigger_in == 0
     }
@@ -86,6 +119,8 @@ impl NoteState {
     pub fn current_raw(&self) -> NoteStateCurrentRaw {
         NoteStateCurrentRaw {
             has_triggered: self.has_triggered(),
+            held: self.held,
+            retriggered: self.retriggered,
             has_ended: self.ended,
             since_trigger: self.seconds_since_triggered(),
             since_release: self.seconds_since_released(),
@@ -95,6 +130,8 @@ impl NoteState {
 
 pub struct NoteStateCurrentRaw {
     pub has_triggered: bool,
+    pub held: bool,
+    pub retriggered: bool,
     pub has_ended: bool,
     pub since_trigger: f32,
     pub since_release: f32,
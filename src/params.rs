@@ -1,12 +1,67 @@
-use std::sync::Arc;
-use std::sync::atomic::AtomicI64;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 
-use nih_plug::prelude::{Params, FloatParam, FloatRange, SmoothingStyle};
+use nih_plug::prelude::{Params, FloatParam, FloatRange, SmoothingStyle, Enum, EnumParam};
 use nih_plug_vizia::ViziaState;
 
+use crate::common_data::WAVETABLE_SLOTS;
+use crate::component::{mseg::MsegShape, lfo::LFOS, lfo_shape::LFOShape};
 use crate::editor;
 use crate::state::text::TextState;
 
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Oversampling {
+    #[name = "1x"]
+    X1,
+    #[name = "2x"]
+    X2,
+    #[name = "4x"]
+    X4,
+    #[name = "8x"]
+    X8,
+}
+impl Oversampling {
+    pub fn factor(&self) -> usize {
+        match self {
+            Self::X1 => 1,
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+        }
+    }
+}
+
+/// How notes are given voices.
+#[derive(Enum, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VoiceMode {
+    /// every note gets a voice of its own
+    Poly,
+    /// one voice, started over by every note
+    Mono,
+    /// one voice, only started over by a note played after the last one was let go
+    Legato,
+}
+
+/// Where one of the shared wavetables is loaded from.
+#[derive(Params)]
+pub struct WavetableSlotParams {
+    #[persist = "path"]
+    pub path: Arc<TextState>,
+
+    /// Changed whenever `path` is picked, so the plugin knows to reload it.
+    #[persist = "path-id"]
+    pub path_id: Arc<AtomicI64>,
+}
+
+impl Default for WavetableSlotParams {
+    fn default() -> Self {
+        Self {
+            path: Arc::new(TextState::default()),
+            path_id: Arc::new(AtomicI64::new(0)),
+        }
+    }
+}
+
 #[derive(Params)]
 pub struct TestParams {
     /// The editor state, saved together with the parameter state so the custom scaling can be
@@ -14,14 +69,57 @@ pub struct TestParams {
     #[persist = "editor-state"]
     pub editor_state: Arc<ViziaState>,
 
+    #[nested(array, group = "Wavetables")]
+    pub wavetables: [WavetableSlotParams; WAVETABLE_SLOTS],
+
+    /// Where the single wavetable was saved before there were slots, read into slot 0 by
+    /// `migrate_legacy_wavetable`.
     #[persist = "yeet-lol"]
-    pub rel: Arc<TextState>,
+    pub legacy_wavetable_path: Arc<TextState>,
 
     #[persist = "yeet-lol-id"]
-    pub rel_id: Arc<AtomicI64>,
+    pub legacy_wavetable_path_id: Arc<AtomicI64>,
+
+    #[persist = "sample-path"]
+    pub sample_path: Arc<TextState>,
+
+    #[persist = "sample-path-id"]
+    pub sample_path_id: Arc<AtomicI64>,
+
+    #[persist = "sfz-path"]
+    pub sfz_path: Arc<TextState>,
+
+    #[persist = "sfz-path-id"]
+    pub sfz_path_id: Arc<AtomicI64>,
+
+    /// Content hash of the instrument and its samples when they were last loaded.
+    #[persist = "sfz-hash"]
+    pub sfz_hash: Arc<AtomicU64>,
+
+    /// Breakpoints of the multi-segment envelope, copied for the voices whenever a state loads.
+    #[persist = "mseg"]
+    pub mseg: Arc<RwLock<MsegShape>>,
+
+    /// Drawn LFO shapes, rendered for the voices in the background whenever they change.
+    #[persist = "lfo-shapes"]
+    pub lfo_shapes: Arc<RwLock<[LFOShape; LFOS]>>,
+
+    /// Bumped by the editor whenever it changes `lfo_shapes`, so the plugin knows to render them.
+    pub lfo_shapes_generation: Arc<AtomicU64>,
 
     #[id = "gain"]
     pub gain: FloatParam,
+
+    #[id = "voice-mode"]
+    pub voice_mode: EnumParam<VoiceMode>,
+
+    /// How many times the host rate voices run at, to keep FM, sync and warping from aliasing.
+    #[id = "oversampling"]
+    pub oversampling: EnumParam<Oversampling>,
+
+    /// Used instead of `oversampling` when the host renders offline.
+    #[id = "offline-oversampling"]
+    pub offline_oversampling: EnumParam<Oversampling>,
 }
 
 impl Default for TestParams {
@@ -39,10 +137,47 @@ impl Default for TestParams {
             .with_step_size(0.01)
             .with_unit(" dB"),
 
+            voice_mode: EnumParam::new("Voice Mode", VoiceMode::Poly),
+
+            oversampling: EnumParam::new("Oversampling", Oversampling::X1),
+            offline_oversampling: EnumParam::new("Offline Oversampling", Oversampling::X4),
+
             editor_state: editor::default_state(),
 
-            rel: Arc::new(TextState::default()),
-            rel_id: Arc::new(AtomicI64::new(0)),
+            wavetables: Default::default(),
+            legacy_wavetable_path: Arc::new(TextState::default()),
+            legacy_wavetable_path_id: Arc::new(AtomicI64::new(0)),
+
+            sample_path: Arc::new(TextState::default()),
+            sample_path_id: Arc::new(AtomicI64::new(0)),
+
+            sfz_path: Arc::new(TextState::default()),
+            sfz_path_id: Arc::new(AtomicI64::new(0)),
+            sfz_hash: Arc::new(AtomicU64::new(0)),
+
+            mseg: Arc::new(RwLock::new(MsegShape::default())),
+            lfo_shapes: Default::default(),
+            lfo_shapes_generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl TestParams {
+    /// Move a wavetable saved under the old keys into slot 0, unless slot 0 has its own. Called
+    /// once a state has been loaded.
+    pub fn migrate_legacy_wavetable(&self) {
+        let legacy_path = self.legacy_wavetable_path.get_v();
+        if legacy_path.is_empty() {
+            return;
+        }
+        let slot = &self.wavetables[0];
+        if slot.path.get_v().is_empty() {
+            slot.path.set_v(legacy_path);
+            // Never 0, so the plugin sees it as picked and loads it.
+            let path_id = self.legacy_wavetable_path_id.load(Ordering::Relaxed).max(1);
+            slot.path_id.store(path_id, Ordering::Relaxed);
         }
+        self.legacy_wavetable_path.set_v(String::new());
+        self.legacy_wavetable_path_id.store(0, Ordering::Relaxed);
     }
 }
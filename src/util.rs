@@ -2,4 +2,10 @@ pub mod lerpable;
 pub mod lx_interp;
 pub mod simple_waveforms;
 pub mod increment_mod;
-pub mod param_range;
\ No newline at end of file
+pub mod param_range;
+pub mod seeded_rng;
+pub mod fnv;
+
+pub mod decimator;
+pub mod note_division;
+pub mod retired_queue;
\ No newline at end of file
@@ -0,0 +1,137 @@
+use std::f32::consts::PI;
+
+/// Zeroth order modified Bessel function of the first kind, for the Kaiser window.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1 .. 32 {
+        term *= (x / (2 * k) as f32).powi(2);
+        sum += term;
+    }
+    sum
+}
+
+/// Halves the sample rate through a Kaiser-windowed sinc halfband lowpass.
+///
+/// Every other tap of a halfband filter is zero, so only the odd taps and the centre are
+/// evaluated, once per output sample.
+pub struct HalfbandDecimator {
+    /// taps `centre ± (2j + 1)`, the centre tap being `0.5`
+    taps: Vec<f32>,
+    /// the last `LEN` inputs, written twice so they can be read without wrapping
+    history: Vec<f32>,
+    pos: usize,
+}
+impl HalfbandDecimator {
+    /// nonzero taps on each side of the centre
+    const K: usize = 16;
+    const LEN: usize = 4 * Self::K - 1;
+    const CENTRE: usize = 2 * Self::K - 1;
+    /// Kaiser window shape, about 80dB of stopband rejection
+    const BETA: f32 = 8.0;
+
+    pub fn new() -> Self {
+        let mut taps: Vec<f32> = (0 .. Self::K).map(|j| {
+            let offset = (2 * j + 1) as f32;
+            let x = offset * 0.5;
+            let sinc = (PI * x).sin() / (PI * x);
+            let r = offset / Self::CENTRE as f32;
+            let kaiser = bessel_i0(Self::BETA * (1.0 - r * r).sqrt()) / bessel_i0(Self::BETA);
+            0.5 * sinc * kaiser
+        }).collect();
+        // Make the gain at DC exactly one.
+        let side: f32 = taps.iter().sum();
+        for tap in &mut taps {
+            *tap *= 0.25 / side;
+        }
+        Self {
+            taps,
+            history: vec![0.0; 2 * Self::LEN],
+            pos: 0,
+        }
+    }
+    fn push(&mut self, x: f32) {
+        self.history[self.pos] = x;
+        self.history[self.pos + Self::LEN] = x;
+        self.pos = (self.pos + 1) % Self::LEN;
+    }
+    /// Take two input samples and give one output sample.
+    pub fn process(&mut self, a: f32, b: f32) -> f32 {
+        self.push(a);
+        self.push(b);
+        // Oldest first, so `window[LEN - 1]` is `b`.
+        let window = &self.history[self.pos .. self.pos + Self::LEN];
+        let centre = Self::LEN - 1 - Self::CENTRE;
+        let mut y = 0.5 * window[centre];
+        for (j, tap) in self.taps.iter().enumerate() {
+            let offset = 2 * j + 1;
+            y += tap * (window[centre - offset] + window[centre + offset]);
+        }
+        y
+    }
+}
+impl Default for HalfbandDecimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Brings audio rendered at `factor` times the sample rate back down, one halfband stage
+/// per factor of two.
+pub struct Decimator {
+    stages: Vec<HalfbandDecimator>,
+}
+impl Decimator {
+    /// `factor` should be a power of two.
+    pub fn new(factor: usize) -> Self {
+        let n_stages = factor.max(1).trailing_zeros() as usize;
+        Self {
+            stages: (0 .. n_stages).map(|_| HalfbandDecimator::new()).collect(),
+        }
+    }
+    /// Decimate `buffer` in place, leaving it `factor` times shorter.
+    pub fn process(&mut self, buffer: &mut Vec<f32>) {
+        for stage in &mut self.stages {
+            let len = buffer.len() / 2;
+            for i in 0 .. len {
+                buffer[i] = stage.process(buffer[2 * i], buffer[2 * i + 1]);
+            }
+            buffer.truncate(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output amplitude once the filter has settled, for a sine at `freq` of the input rate.
+    fn gain(factor: usize, freq: f64) -> f32 {
+        let mut decimator = Decimator::new(factor);
+        let mut buffer: Vec<f32> = (0 .. 8192 * factor)
+            .map(|i| (i as f64 * freq * std::f64::consts::TAU).sin() as f32)
+            .collect();
+        decimator.process(&mut buffer);
+        assert_eq!(buffer.len(), 8192);
+        let settled = &buffer[1024 ..];
+        (settled.iter().map(|v| v * v).sum::<f32>() / settled.len() as f32 * 2.0).sqrt()
+    }
+
+    #[test]
+    fn passes_dc() {
+        let mut decimator = Decimator::new(4);
+        let mut buffer = vec![1.0; 1024];
+        decimator.process(&mut buffer);
+        assert!((buffer[255] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn passes_band_and_rejects_images() {
+        // Well inside the output band.
+        assert!((gain(2, 0.05) - 1.0).abs() < 0.01);
+        assert!((gain(8, 0.01) - 1.0).abs() < 0.01);
+        // Would alias into the output band.
+        assert!(gain(2, 0.4) < 1e-3);
+        assert!(gain(4, 0.3) < 1e-3);
+    }
+}
@@ -0,0 +1,23 @@
+/// FNV-1a, a stable hash so content hashes stored in plugin state survive rebuilds.
+pub struct Fnv1a64 {
+    state: u64,
+}
+impl Fnv1a64 {
+    pub fn new() -> Self {
+        Self { state: 0xCBF2_9CE4_8422_2325 }
+    }
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+    }
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+impl Default for Fnv1a64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,13 +1,14 @@
-pub fn increment_mod_01_f32(var: &mut f32, delta: f32) {
+/// Returns `true` if the value wrapped around past `1.0`.
+pub fn increment_mod_01_f32(var: &mut f32, delta: f32) -> bool {
     *var += delta;
-    if *var >= 1.0 {
-        *var -= 1.0;
-    }
-    if *var < 0.0 {
-        *var += 1.0;
+    let wrapped = *var >= 1.0;
+    if wrapped || *var < 0.0 {
+        // `rem_euclid` can round up to exactly 1.0 for tiny negative values.
+        *var = var.rem_euclid(1.0) % 1.0;
     }
+    wrapped
 }
 
-pub fn increment_phase(phase: &mut f32, sample_rate: f32, freq: f32) {
-    increment_mod_01_f32(phase, freq/sample_rate);
-}
\ No newline at end of file
+pub fn increment_phase(phase: &mut f32, sample_rate: f32, freq: f32) -> bool {
+    increment_mod_01_f32(phase, freq/sample_rate)
+}
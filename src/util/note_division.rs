@@ -0,0 +1,54 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NoteModifier {
+    Straight,
+    Dotted,
+    Triplet,
+}
+
+/// A note length a tempo synced rate follows, like a dotted eighth or two bars.
+#[derive(Clone, Copy)]
+pub struct NoteDivision {
+    /// in whole notes, a whole note being a bar of 4/4
+    whole_notes: f32,
+    modifier: NoteModifier,
+}
+impl NoteDivision {
+    pub const SHORTEST: f32 = 1.0 / 64.0;
+    pub const LONGEST: f32 = 8.0;
+    /// `whole_notes` is kept between a 1/64 note and 8 bars.
+    pub fn new(whole_notes: f32, modifier: NoteModifier) -> Self {
+        Self { whole_notes: whole_notes.clamp(Self::SHORTEST, Self::LONGEST), modifier }
+    }
+    /// Length in quarter note beats, which host song positions count in.
+    pub fn beats(&self) -> f32 {
+        self.whole_notes * 4.0 * match self.modifier {
+            NoteModifier::Straight => 1.0,
+            NoteModifier::Dotted => 1.5,
+            NoteModifier::Triplet => 2.0 / 3.0,
+        }
+    }
+    /// Cycles per second at `tempo` BPM.
+    pub fn freq(&self, tempo: f32) -> f32 {
+        tempo / 60.0 / self.beats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifiers_scale_length() {
+        let eighth = NoteDivision::new(1.0 / 8.0, NoteModifier::Straight);
+        assert_eq!(eighth.beats(), 0.5);
+        assert_eq!(NoteDivision::new(1.0 / 8.0, NoteModifier::Dotted).beats(), 0.75);
+        assert!((NoteDivision::new(1.0 / 4.0, NoteModifier::Triplet).beats() - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(eighth.freq(120.0), 4.0);
+    }
+
+    #[test]
+    fn clamps_to_range() {
+        assert_eq!(NoteDivision::new(100.0, NoteModifier::Straight).beats(), 32.0);
+        assert_eq!(NoteDivision::new(0.0, NoteModifier::Straight).beats(), 1.0 / 16.0);
+    }
+}
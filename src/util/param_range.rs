@@ -26,6 +26,13 @@ impl ParamRange {
                 ((x+virtual_min)/virtual_min).ln()/base,
         }.clamp(0.0, 1.0)
     }
+    /// Normalized offset that detunes a frequency in an exponential range by `cents`.
+    pub fn cents_offset(&self, cents: f32) -> f32 {
+        match self {
+            Self::Exponential { base, .. } => cents / 1200.0 * std::f32::consts::LN_2 / base,
+            Self::Linear { .. } | Self::ExponentialToZero { .. } => 0.0,
+        }
+    }
     pub fn denormalize(&self, y: f32) -> f32 {
         let y = y.clamp(0.0, 1.0);
         match self {
@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use crossbeam::queue::SegQueue;
+
+/// Shared data swapped out while voices may still hold it. The queue keeps a reference of its
+/// own, so a voice letting go on the audio thread is never the last one, and `free_unused`
+/// frees it off the audio thread once nothing else holds it.
+pub struct RetiredQueue<T> {
+    queue: SegQueue<Arc<T>>,
+}
+impl<T> Default for RetiredQueue<T> {
+    fn default() -> Self {
+        Self { queue: SegQueue::new() }
+    }
+}
+impl<T> RetiredQueue<T> {
+    /// Hold on to `old` until it is unused. Retire the same data only once, as two entries
+    /// would keep each other alive. Pushing may allocate, so call off the audio thread.
+    pub fn retire(&self, old: Arc<T>) {
+        self.queue.push(old);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+    /// Free everything nothing else holds any more. Call off the audio thread.
+    pub fn free_unused(&self) {
+        for _ in 0 .. self.queue.len() {
+            if let Some(old) = self.queue.pop() {
+                // Nothing can pick up retired data again, so once the queue holds the only
+                // reference no voice will touch it.
+                if Arc::strong_count(&old) > 1 {
+                    self.queue.push(old);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frees_once_unused() {
+        let retired = RetiredQueue::default();
+        let held = Arc::new(vec![0.0f32; 4]);
+        retired.retire(held.clone());
+        retired.retire(Arc::new(vec![1.0f32; 4]));
+
+        retired.free_unused();
+        assert!(!retired.is_empty());
+        assert_eq!(Arc::strong_count(&held), 2);
+
+        drop(held);
+        retired.free_unused();
+        assert!(retired.is_empty());
+    }
+}